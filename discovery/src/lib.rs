@@ -0,0 +1,228 @@
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::{self, FromStr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use thiserror::Error;
+
+pub const DISCOVERY_PORT: u16 = 10710;
+pub const DEFAULT_DISCOVERY_ADDRESS: &str = "0.0.0.0:10710";
+pub const BROADCAST_ADDRESS: &str = "255.255.255.255:10710";
+
+/// Datagram a client broadcasts to find devices on the LAN.
+pub const PROBE: &str = "discover";
+
+const ANNOUNCEMENT_PREFIX: &str = "device";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    SmartSocket,
+    Thermometer,
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceKind::SmartSocket => write!(f, "socket"),
+            DeviceKind::Thermometer => write!(f, "thermometer"),
+        }
+    }
+}
+
+impl FromStr for DeviceKind {
+    type Err = AnnouncementError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "socket" => Ok(DeviceKind::SmartSocket),
+            "thermometer" => Ok(DeviceKind::Thermometer),
+            other => Err(AnnouncementError::UnknownKind(other.to_owned())),
+        }
+    }
+}
+
+/// A device's answer to a probe: `device\t<kind>\t<name>\t<address>`.
+///
+/// For a smart socket the address is the one its TCP server listens on,
+/// for a thermometer it is the address its telemetry is sent from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Announcement {
+    pub kind: DeviceKind,
+    pub name: String,
+    pub address: SocketAddr,
+}
+
+impl fmt::Display for Announcement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            ANNOUNCEMENT_PREFIX, self.kind, self.name, self.address
+        )
+    }
+}
+
+impl FromStr for Announcement {
+    type Err = AnnouncementError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_end().split('\t').collect::<Vec<_>>()[..] {
+            [ANNOUNCEMENT_PREFIX, kind, name, address] if !name.is_empty() => Ok(Announcement {
+                kind: kind.parse()?,
+                name: name.to_owned(),
+                address: address
+                    .parse()
+                    .map_err(|_| AnnouncementError::Malformed(s.to_owned()))?,
+            }),
+            _ => Err(AnnouncementError::Malformed(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AnnouncementError {
+    #[error("unknown device kind: {0}")]
+    UnknownKind(String),
+    #[error("malformed announcement: {0}")]
+    Malformed(String),
+}
+
+/// Answers discovery probes on behalf of one device until dropped.
+///
+/// Several responders may share the discovery port on one host.
+#[derive(Debug)]
+pub struct Responder {
+    local_addr: SocketAddr,
+    done: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Responder {
+    pub fn spawn<Addrs>(addr: Addrs, announcement: Announcement) -> io::Result<Self>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let socket = bind_shared(addr)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let local_addr = socket.local_addr()?;
+        let done = Arc::new(AtomicBool::new(false));
+
+        let _done = done.clone();
+        let handle = thread::spawn(move || {
+            let reply = announcement.to_string();
+            let mut buf = [0; 64];
+            while !_done.load(Ordering::Relaxed) {
+                match socket.recv_from(&mut buf) {
+                    Ok((n, from)) if &buf[..n] == PROBE.as_bytes() => {
+                        if let Err(e) = socket.send_to(reply.as_bytes(), from) {
+                            println!("cannot answer probe from {}: {}", from, e);
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut => {}
+                    Err(e) => {
+                        println!("discovery responder stopped: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            done,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn bind_shared<Addrs: ToSocketAddrs>(addr: Addrs) -> io::Result<UdpSocket> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to bind"))?;
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement_roundtrip() {
+        let announcement = Announcement {
+            kind: DeviceKind::Thermometer,
+            name: "thermometer on the wall".into(),
+            address: "127.0.0.1:11601".parse().unwrap(),
+        };
+        assert_eq!(
+            announcement.to_string().parse::<Announcement>().unwrap(),
+            announcement
+        );
+        assert!("device\tlamp\tx\t127.0.0.1:1"
+            .parse::<Announcement>()
+            .is_err());
+        assert!("device\tsocket\t127.0.0.1:1"
+            .parse::<Announcement>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_responders_share_port() {
+        let first = Responder::spawn(
+            "127.0.0.1:0",
+            Announcement {
+                kind: DeviceKind::SmartSocket,
+                name: "socket".into(),
+                address: "127.0.0.1:10701".parse().unwrap(),
+            },
+        )
+        .unwrap();
+        let _second = Responder::spawn(
+            first.local_addr(),
+            Announcement {
+                kind: DeviceKind::Thermometer,
+                name: "thermometer".into(),
+                address: "127.0.0.1:11601".parse().unwrap(),
+            },
+        )
+        .unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client
+            .send_to(PROBE.as_bytes(), first.local_addr())
+            .unwrap();
+        let mut buf = [0; 256];
+        let (n, _) = client.recv_from(&mut buf).unwrap();
+        let announcement: Announcement = str::from_utf8(&buf[..n]).unwrap().parse().unwrap();
+        assert!(["socket", "thermometer"].contains(&announcement.name.as_str()));
+    }
+}
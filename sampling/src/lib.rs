@@ -0,0 +1,115 @@
+//! Building blocks shared by the simulated devices: a bounded random walk
+//! and replaying `seconds,value` rows of a CSV file in a loop.
+
+use std::time::Duration;
+
+use rand::Rng;
+use thiserror::Error;
+
+/// Moves `last` by at most `step`, staying in `min..=max`.
+pub fn random_walk(last: f64, min: f64, max: f64, step: f64) -> f64 {
+    let delta = if step > 0.0 {
+        rand::thread_rng().gen_range(-step..=step)
+    } else {
+        0.0
+    };
+    (last + delta).clamp(min, max)
+}
+
+/// The value of `samples` at `elapsed`, looping over them.
+///
+/// The last sample marks where the loop ends: at its time the replay starts
+/// over from the first one, so its value is never returned unless it is the
+/// only sample.
+pub fn replay(samples: &[(Duration, f64)], elapsed: Duration) -> f64 {
+    let period = samples.last().map(|s| s.0).unwrap_or_default();
+    let offset = if period.is_zero() {
+        Duration::ZERO
+    } else {
+        Duration::from_nanos((elapsed.as_nanos() % period.as_nanos()) as u64)
+    };
+    samples
+        .iter()
+        .take_while(|s| s.0 <= offset)
+        .last()
+        .map(|s| s.1)
+        .unwrap_or(0.0)
+}
+
+/// Parses `seconds,value` rows, sorted by time. Blank lines, `#` comments
+/// and a header row are skipped.
+pub fn parse_csv(content: &str) -> Result<Vec<(Duration, f64)>, InvalidCsvRow> {
+    let mut samples = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.split_once(',').and_then(|(seconds, value)| {
+            Some((
+                seconds.trim().parse::<f64>().ok()?,
+                value.trim().parse::<f64>().ok()?,
+            ))
+        });
+        let parsed = parsed.map(|(seconds, value)| {
+            (
+                Duration::try_from_secs_f64(seconds).ok(),
+                Some(value).filter(|v| v.is_finite()),
+            )
+        });
+        match parsed {
+            Some((Some(at), Some(value))) => samples.push((at, value)),
+            // allow a header row
+            None if idx == 0 => continue,
+            _ => return Err(InvalidCsvRow(idx + 1)),
+        }
+    }
+    if samples.is_empty() {
+        return Err(InvalidCsvRow(0));
+    }
+    samples.sort_by_key(|s| s.0);
+    Ok(samples)
+}
+
+/// The 1-based number of the row that could not be parsed, 0 if there were
+/// no rows at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("invalid csv row {0}")]
+pub struct InvalidCsvRow(pub usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_walk_stays_in_bounds() {
+        let mut value = 15.0;
+        for _ in 0..1000 {
+            value = random_walk(value, 10.0, 20.0, 5.0);
+            assert!((10.0..=20.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn test_replay_loops_over_samples() {
+        let samples = parse_csv("seconds,value\n0,100\n10,5\n20,100\n").unwrap();
+        let at = |secs| replay(&samples, Duration::from_secs(secs));
+        assert_eq!(at(3), 100.0);
+        assert_eq!(at(15), 5.0);
+        assert_eq!(at(25), 100.0);
+        assert_eq!(at(32), 5.0);
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        assert_eq!(
+            parse_csv("# comment\n\n10,2\n0,1\n").unwrap(),
+            vec![(Duration::ZERO, 1.0), (Duration::from_secs(10), 2.0)]
+        );
+        assert_eq!(parse_csv("0,1\nbroken\n"), Err(InvalidCsvRow(2)));
+        assert_eq!(parse_csv("0,1\n1e400,5\n"), Err(InvalidCsvRow(2)));
+        assert_eq!(parse_csv("0,1\n-1,5\n"), Err(InvalidCsvRow(2)));
+        assert_eq!(parse_csv("0,NaN\n"), Err(InvalidCsvRow(1)));
+        assert_eq!(parse_csv("seconds,value\n"), Err(InvalidCsvRow(0)));
+    }
+}
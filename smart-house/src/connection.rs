@@ -1,11 +1,106 @@
+use std::fmt;
 use std::io;
+use std::time::{Duration, SystemTime};
 
+use smart_socket::protocol::{FrameError, ParseError};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ConnectError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
+    #[error("no response within {0:?}")]
+    Timeout(Duration),
+    #[error("framing error: {0}")]
+    Frame(#[from] FrameError),
+    #[error("malformed response: {0}")]
+    Parse(#[from] ParseError),
+    #[error("device error {code}: {message}")]
+    Remote { code: u16, message: String },
+    #[error("unexpected response: {0}")]
+    UnexpectedResponse(String),
+    #[error("device is failing, next try in {0:?}")]
+    CircuitOpen(Duration),
 }
 
 pub type ConnectResult<T> = Result<T, ConnectError>;
+
+/// Health of the link to a device as last seen by its client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    /// `connect` has not been called yet.
+    Disconnected,
+    Connected,
+    /// The link was lost and the client is trying to get it back.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// The last exchange or reconnect failed. The next request tries again.
+    Down {
+        error: String,
+        at: SystemTime,
+    },
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Disconnected => write!(f, "disconnected"),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Reconnecting { attempt } => {
+                write!(f, "reconnecting (attempt {})", attempt)
+            }
+            ConnectionState::Down { error, at } => write!(
+                f,
+                "down for {}s: {}",
+                at.elapsed().unwrap_or_default().as_secs(),
+                error
+            ),
+        }
+    }
+}
+
+/// Delays between reconnect attempts, doubling from `initial` up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// How many times to try before giving up, at least one.
+    pub attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(2),
+            attempts: 5,
+        }
+    }
+}
+
+impl Backoff {
+    /// The pause after the failed attempt number `attempt`, counting from zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .checked_mul(1 << attempt.min(16))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(500),
+            attempts: 10,
+        };
+        let delays: Vec<_> = (0..5).map(|a| backoff.delay(a).as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+        assert_eq!(backoff.delay(u32::MAX), backoff.max);
+    }
+}
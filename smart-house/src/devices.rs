@@ -1,5 +1,7 @@
+pub mod circuit_breaker;
 pub mod device;
 pub mod retriable_switcher;
+pub mod sensor;
 pub mod smartsocket;
 pub mod thermometer;
 pub mod types;
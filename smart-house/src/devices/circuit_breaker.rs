@@ -0,0 +1,234 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::connection::{ConnectError, ConnectResult};
+use crate::retry::is_transient;
+
+use super::device::Switcher;
+
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_COOL_DOWN: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    /// Calls go through. `failures` is the number of them failed in a row.
+    Closed { failures: u32 },
+    /// Calls are refused without reaching the device.
+    Open { retry_in: Duration },
+    /// The cool-down is over, the next call decides whether to close again.
+    HalfOpen,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerState::Closed { .. } => write!(f, "closed"),
+            BreakerState::Open { retry_in } => write!(f, "open (retry in {}s)", retry_in.as_secs()),
+            BreakerState::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    failures: u32,
+    opened_at: Option<Instant>,
+    trial_started: Option<Instant>,
+}
+
+/// Stops calling a device after `failure_threshold` failures in a row and
+/// lets a single trial call through once `cool_down` has passed.
+///
+/// Only failures `retry::is_transient` accepts count: a device rejecting
+/// a request is reachable after all.
+#[derive(Debug)]
+pub struct Breaker {
+    failure_threshold: u32,
+    cool_down: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOL_DOWN)
+    }
+}
+
+impl Breaker {
+    pub fn new(failure_threshold: u32, cool_down: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cool_down,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        let inner = self.inner.lock().unwrap();
+        match inner.opened_at {
+            None => BreakerState::Closed {
+                failures: inner.failures,
+            },
+            Some(at) if at.elapsed() < self.cool_down => BreakerState::Open {
+                retry_in: self.cool_down - at.elapsed(),
+            },
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    /// Awaits `op` unless the circuit is open, in which case it fails with
+    /// `ConnectError::CircuitOpen` right away.
+    pub async fn call<T, Fut>(&self, op: Fut) -> ConnectResult<T>
+    where
+        Fut: Future<Output = ConnectResult<T>>,
+    {
+        self.acquire()?;
+        let res = op.await;
+        self.record(res.as_ref().err());
+        res
+    }
+
+    fn acquire(&self) -> ConnectResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let opened_at = match inner.opened_at {
+            None => return Ok(()),
+            Some(at) => at,
+        };
+        let elapsed = opened_at.elapsed();
+        if elapsed < self.cool_down {
+            return Err(ConnectError::CircuitOpen(self.cool_down - elapsed));
+        }
+        // one trial at a time; a trial that never reported back does not
+        // block the breaker for longer than another cool-down
+        match inner.trial_started {
+            Some(at) if at.elapsed() < self.cool_down => {
+                Err(ConnectError::CircuitOpen(self.cool_down - at.elapsed()))
+            }
+            _ => {
+                inner.trial_started = Some(Instant::now());
+                Ok(())
+            }
+        }
+    }
+
+    fn record(&self, error: Option<&ConnectError>) {
+        let mut inner = self.inner.lock().unwrap();
+        let was_trial = inner.trial_started.take().is_some();
+        match error {
+            Some(e) if is_transient(e) => {
+                inner.failures += 1;
+                if was_trial || inner.failures >= self.failure_threshold {
+                    inner.opened_at = Some(Instant::now());
+                }
+            }
+            _ => {
+                inner.failures = 0;
+                inner.opened_at = None;
+            }
+        }
+    }
+}
+
+/// Switcher decorator refusing to switch a device that keeps failing.
+pub struct CircuitBreaker {
+    breaker: Breaker,
+    inner: Box<dyn Switcher + Send>,
+}
+
+impl CircuitBreaker {
+    pub fn new(inner: Box<dyn Switcher + Send>, breaker: Breaker) -> Self {
+        Self { breaker, inner }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.breaker.state()
+    }
+}
+
+#[async_trait::async_trait]
+impl Switcher for CircuitBreaker {
+    async fn switch(&mut self) -> ConnectResult<()> {
+        self.breaker.call(self.inner.switch()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    fn unreachable() -> ConnectResult<()> {
+        Err(ConnectError::Io(io::ErrorKind::ConnectionRefused.into()))
+    }
+
+    #[test]
+    fn test_breaker_opens_and_half_opens() {
+        let breaker = Breaker::new(2, Duration::from_millis(50));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert!(breaker.call(async { unreachable() }).await.is_err());
+            assert_eq!(breaker.state(), BreakerState::Closed { failures: 1 });
+            assert!(breaker.call(async { unreachable() }).await.is_err());
+            assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+
+            let res: ConnectResult<()> = breaker.call(async { panic!("must not be called") }).await;
+            assert!(matches!(res, Err(ConnectError::CircuitOpen(_))));
+
+            // a failed trial opens the circuit again at once
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            assert_eq!(breaker.state(), BreakerState::HalfOpen);
+            assert!(breaker.call(async { unreachable() }).await.is_err());
+            assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            breaker.call(async { Ok(()) }).await.unwrap();
+            assert_eq!(breaker.state(), BreakerState::Closed { failures: 0 });
+        });
+    }
+
+    #[test]
+    fn test_rejections_do_not_open_breaker() {
+        let breaker = Breaker::new(1, Duration::from_secs(60));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let res: ConnectResult<()> = breaker
+                .call(async {
+                    Err(ConnectError::Remote {
+                        code: 401,
+                        message: "authentication required".into(),
+                    })
+                })
+                .await;
+            assert!(res.is_err());
+            assert_eq!(breaker.state(), BreakerState::Closed { failures: 0 });
+        });
+    }
+
+    struct Unreachable;
+
+    #[async_trait::async_trait]
+    impl Switcher for Unreachable {
+        async fn switch(&mut self) -> ConnectResult<()> {
+            unreachable()
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker_switcher() {
+        let mut switcher = CircuitBreaker::new(
+            Box::new(Unreachable),
+            Breaker::new(1, Duration::from_secs(60)),
+        );
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert!(matches!(switcher.switch().await, Err(ConnectError::Io(_))));
+            assert!(matches!(
+                switcher.switch().await,
+                Err(ConnectError::CircuitOpen(_))
+            ));
+        });
+    }
+}
@@ -8,6 +8,13 @@ pub trait Summary {
     async fn summary(&self) -> String;
 }
 
+/// Like `Summary`, but tells a device that could not be asked apart from
+/// one that answered.
+#[async_trait::async_trait]
+pub trait TrySummary {
+    async fn try_summary(&self) -> ConnectResult<String>;
+}
+
 pub trait Device {
     fn get_name(&self) -> &str;
     fn get_description(&self) -> &str;
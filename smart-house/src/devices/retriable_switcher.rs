@@ -1,29 +1,97 @@
-use crate::connection::ConnectResult;
+use std::convert::TryInto;
+
+use tokio::time;
+
+use crate::connection::{Backoff, ConnectResult};
+use crate::retry::RetryPolicy;
 
 use super::device::Switcher;
 
 pub struct Retry {
-    attempts: usize,
+    policy: RetryPolicy,
     inner: Box<dyn Switcher + Send>,
 }
 
 impl Retry {
+    /// Makes up to `attempts` attempts with the default backoff.
     pub fn new(inner: Box<dyn Switcher + Send>, attempts: usize) -> Self {
-        Self { inner, attempts }
+        let policy = RetryPolicy {
+            backoff: Backoff {
+                attempts: attempts.max(1).try_into().unwrap_or(u32::MAX),
+                ..Backoff::default()
+            },
+            ..RetryPolicy::default()
+        };
+        Self::with_policy(inner, policy)
+    }
+
+    pub fn with_policy(inner: Box<dyn Switcher + Send>, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
     }
 }
 
 #[async_trait::async_trait]
 impl Switcher for Retry {
     async fn switch(&mut self) -> ConnectResult<()> {
-        let mut res: ConnectResult<()> = Ok(());
-        for i in 0..self.attempts {
-            res = self.inner.switch().await;
-            if let Ok(()) = res {
-                println!("succeed on the {} attempt", i + 1);
-                break;
+        let mut state = self.policy.start();
+        loop {
+            match self.inner.switch().await {
+                Err(e) => match state.next_delay(&e) {
+                    Some(delay) => time::sleep(delay).await,
+                    None => return Err(e),
+                },
+                ok => return ok,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::connection::ConnectError;
+
+    struct Flaky {
+        failures: u32,
+    }
+
+    #[async_trait::async_trait]
+    impl Switcher for Flaky {
+        async fn switch(&mut self) -> ConnectResult<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(ConnectError::Io(io::ErrorKind::ConnectionReset.into()));
             }
+            Ok(())
         }
-        res
+    }
+
+    #[test]
+    fn test_retry_switch() {
+        let policy = RetryPolicy {
+            backoff: Backoff {
+                initial: Duration::from_millis(1),
+                max: Duration::from_millis(1),
+                attempts: 3,
+            },
+            ..RetryPolicy::default()
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let flaky = Flaky { failures: 2 };
+            Retry::with_policy(Box::new(flaky), policy)
+                .switch()
+                .await
+                .unwrap();
+
+            let flaky = Flaky { failures: 3 };
+            assert!(Retry::with_policy(Box::new(flaky), policy)
+                .switch()
+                .await
+                .is_err());
+        });
     }
 }
@@ -0,0 +1,232 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use thermometer::datagram::{MeasurementKind, TemperatureUnit};
+
+use crate::connection::ConnectResult;
+use crate::devices::thermometer::DEFAULT_STALE_AFTER;
+use crate::history::{Point, Stats};
+use crate::receiver::Reading;
+use crate::{devices::device::Device, receiver::Receiver};
+
+use super::device::{Summary, TrySummary};
+
+/// A sensor sending several kinds of measurements under one name, e.g. a
+/// weather station reporting temperature, humidity and pressure.
+#[derive(Debug)]
+pub struct Sensor {
+    name: String,
+    description: String,
+    receiver: Arc<Mutex<Option<Receiver>>>,
+    stale_after: Duration,
+    units: TemperatureUnit,
+}
+
+impl Sensor {
+    pub fn new(name: &str, description: &str) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            receiver: Arc::new(Mutex::new(None)),
+            stale_after: DEFAULT_STALE_AFTER,
+            units: TemperatureUnit::default(),
+        }
+    }
+
+    pub fn add_receiver(&mut self, receiver: Arc<Mutex<Option<Receiver>>>) {
+        self.receiver = receiver;
+    }
+
+    pub fn set_stale_after(&mut self, stale_after: Duration) {
+        self.stale_after = stale_after;
+    }
+
+    /// Sets the unit the summary shows temperatures in, °C by default.
+    pub fn set_units(&mut self, units: TemperatureUnit) {
+        self.units = units;
+    }
+
+    pub fn units(&self) -> TemperatureUnit {
+        self.units
+    }
+
+    /// The last reading of `kind` in the kind's default unit, however old it is.
+    pub fn get_measurement(&self, kind: MeasurementKind) -> Option<Reading> {
+        self.receiver
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|receiver| receiver.get_measurement(&self.name, kind))
+    }
+
+    /// The last reading of every kind the sensor sent, ordered by kind.
+    pub fn get_measurements(&self) -> Vec<(MeasurementKind, Reading)> {
+        self.receiver
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|receiver| receiver.get_measurements(&self.name))
+            .unwrap_or_default()
+    }
+
+    pub fn is_stale(&self, reading: &Reading) -> bool {
+        reading.age() > self.stale_after
+    }
+
+    /// The current value of `kind`, `None` if nothing or nothing recent arrived.
+    pub fn get_value(&self, kind: MeasurementKind) -> Option<f64> {
+        self.get_measurement(kind)
+            .filter(|reading| !self.is_stale(reading))
+            .map(|reading| reading.value)
+    }
+
+    pub fn history(&self, kind: MeasurementKind, window: Duration) -> Vec<Point> {
+        self.receiver
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|receiver| receiver.get_history(&self.name, kind, since(window)))
+            .unwrap_or_default()
+    }
+
+    /// Trend is in units per hour.
+    pub fn stats(&self, kind: MeasurementKind, window: Duration) -> Option<Stats> {
+        self.receiver
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|receiver| receiver.get_stats(&self.name, kind, since(window)))
+    }
+
+    fn describe(&self) -> String {
+        self.describe_in(self.units)
+    }
+
+    pub(crate) fn describe_in(&self, units: TemperatureUnit) -> String {
+        let measurements = self.get_measurements();
+        if measurements.is_empty() {
+            return "no data".into();
+        }
+        measurements
+            .into_iter()
+            .map(|(kind, reading)| format!("{}: {}", kind, self.describe_reading(reading, units)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// `reading`, converted to `units` if it is a temperature, or how long
+    /// ago it arrived if it is stale.
+    pub(crate) fn describe_reading(&self, reading: Reading, units: TemperatureUnit) -> String {
+        if self.is_stale(&reading) {
+            format!("stale (last {} ago)", format_age(reading.age()))
+        } else {
+            reading.to(units.into()).unwrap_or(reading).to_string()
+        }
+    }
+}
+
+fn since(window: Duration) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(window)
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+fn format_age(age: Duration) -> String {
+    match age.as_secs() {
+        secs if secs < 60 => format!("{} s", secs),
+        secs if secs < 60 * 60 => format!("{} min", secs / 60),
+        secs if secs < 24 * 60 * 60 => format!("{} h", secs / (60 * 60)),
+        secs => format!("{} d", secs / (24 * 60 * 60)),
+    }
+}
+
+impl Device for Sensor {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+    fn get_description(&self) -> &str {
+        &self.description
+    }
+}
+
+#[async_trait::async_trait]
+impl TrySummary for Sensor {
+    async fn try_summary(&self) -> ConnectResult<String> {
+        Ok(self.describe())
+    }
+}
+
+#[async_trait::async_trait]
+impl Summary for Sensor {
+    async fn summary(&self) -> String {
+        self.describe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::SystemTime;
+
+    use thermometer::datagram::Telemetry;
+
+    use super::*;
+
+    #[test]
+    fn test_summary_lists_measurements() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let receiver = Receiver::new("127.0.0.1:0").await.unwrap();
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            for (kind, value) in [
+                (MeasurementKind::Co2, 600.0),
+                (MeasurementKind::Temperature, 20.0),
+                (MeasurementKind::Humidity, 45.5),
+                (MeasurementKind::Pressure, 1013.2),
+                (MeasurementKind::Battery, 80.0),
+            ] {
+                let telemetry = Telemetry {
+                    device: "weather station".into(),
+                    kind,
+                    unit: kind.unit(),
+                    value,
+                    seq: 0,
+                    sent_at: SystemTime::now(),
+                };
+                socket
+                    .send_to(&telemetry.encode(), receiver.local_addr())
+                    .unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let mut sensor = Sensor::new("weather station", "");
+            assert_eq!(sensor.summary().await, "no data");
+            sensor.add_receiver(Arc::new(Mutex::new(Some(receiver))));
+            assert_eq!(
+                sensor.summary().await,
+                "temperature: 20°C, humidity: 45.5%, pressure: 1013.2 hPa, co2: 600 ppm, battery: 80%"
+            );
+            sensor.set_units(TemperatureUnit::Fahrenheit);
+            assert!(sensor
+                .summary()
+                .await
+                .starts_with("temperature: 68°F, humidity: 45.5%"));
+            assert_eq!(sensor.get_value(MeasurementKind::Co2), Some(600.0));
+            assert_eq!(sensor.get_value(MeasurementKind::Temperature), Some(20.0));
+
+            sensor.set_stale_after(Duration::ZERO);
+            assert_eq!(sensor.get_value(MeasurementKind::Co2), None);
+            assert!(sensor
+                .summary()
+                .await
+                .starts_with("temperature: stale (last 0 s ago), humidity: stale"));
+        });
+    }
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(Duration::from_secs(59)), "59 s");
+        assert_eq!(format_age(Duration::from_secs(5 * 60 + 30)), "5 min");
+        assert_eq!(format_age(Duration::from_secs(3 * 60 * 60)), "3 h");
+        assert_eq!(format_age(Duration::from_secs(2 * 24 * 60 * 60)), "2 d");
+    }
+}
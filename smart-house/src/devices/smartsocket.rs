@@ -1,27 +1,74 @@
-use std::io::{ErrorKind, Read, Write};
-use std::net::TcpStream;
-use std::str;
-use std::sync::{Arc, Mutex};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use regex::Regex;
-use smart_socket::protocol::ProtocolCommand;
+use smart_socket::auth;
+use smart_socket::protocol::{encode, FrameDecoder, ProtocolCommand, ProtocolResponse};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::{self, Instant};
+use tokio_rustls::rustls::{ClientConfig, ServerName};
+use tokio_rustls::TlsConnector;
 
-use crate::connection::{ConnectError, ConnectResult};
+use crate::connection::{Backoff, ConnectError, ConnectResult, ConnectionState};
+use crate::devices::circuit_breaker::{Breaker, BreakerState};
 use crate::devices::device::Device;
+use crate::retry::RetryPolicy;
 
-use super::device::{Summary, Switcher};
+use super::device::{Summary, Switcher, TrySummary};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Either a plain TCP stream or a TLS one on top of it.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug> Stream for T {}
+
+#[derive(Debug)]
+struct Connection {
+    stream: Box<dyn Stream>,
+    decoder: FrameDecoder,
+}
+
+impl Connection {
+    async fn request(&mut self, command: &ProtocolCommand) -> ConnectResult<ProtocolResponse> {
+        self.stream.write_all(&encode(command)).await?;
+        let mut buf = [0; 256];
+        loop {
+            if let Some(frame) = self.decoder.decode()? {
+                return Ok(frame.parse()?);
+            }
+            match self.stream.read(&mut buf).await? {
+                0 => return Err(ConnectError::Io(ErrorKind::UnexpectedEof.into())),
+                n => self.decoder.extend(&buf[..n]),
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct SmartSocket {
     name: String,
     description: String,
-    stream: Arc<Mutex<Option<TcpStream>>>,
+    connection: Arc<Mutex<Option<Connection>>>,
+    timeout: Duration,
+    secret: Option<String>,
+    tls: Option<Arc<ClientConfig>>,
+    /// Remembered by `connect` to get the link back after it is lost.
+    addr: Option<String>,
+    backoff: Backoff,
+    health: std::sync::Mutex<ConnectionState>,
+    retry: RetryPolicy,
+    breaker: Breaker,
 }
 
 #[derive(Debug, Default)]
 pub struct SocketState {
     is_on: bool,
-    power_consumption: usize,
+    power_consumption: f64,
 }
 
 impl SmartSocket {
@@ -29,49 +76,226 @@ impl SmartSocket {
         Self {
             name: name.into(),
             description: description.into(),
-            stream: Arc::new(Mutex::new(None)),
+            connection: Arc::new(Mutex::new(None)),
+            timeout: DEFAULT_TIMEOUT,
+            secret: None,
+            tls: None,
+            addr: None,
+            backoff: Backoff::default(),
+            health: std::sync::Mutex::new(ConnectionState::Disconnected),
+            retry: RetryPolicy::never(),
+            breaker: Breaker::default(),
         }
     }
 
+    /// Sets the time limit for connecting and for every single request,
+    /// getting a lost connection back included.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the secret shared with a socket that requires authentication.
+    /// It is used on the next `connect`.
+    pub fn set_secret(&mut self, secret: &str) {
+        self.secret = Some(secret.into());
+    }
+
+    /// Makes the next `connect` talk TLS, checking the socket's certificate
+    /// against `config`. See `smart_socket::tls::client_config`.
+    pub fn set_tls(&mut self, config: Arc<ClientConfig>) {
+        self.tls = Some(config);
+    }
+
+    /// Sets how a lost connection is re-established.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    /// Sets how status reads and `turn_on`/`turn_off` are retried.
+    /// Switching is not idempotent and is never retried here, wrap the socket
+    /// into `retriable_switcher::Retry` for that.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Replaces the breaker that keeps an unreachable socket from being
+    /// called over and over.
+    pub fn set_breaker(&mut self, breaker: Breaker) {
+        self.breaker = breaker;
+    }
+
+    pub fn breaker_state(&self) -> BreakerState {
+        self.breaker.state()
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.health.lock().unwrap().clone()
+    }
+
+    /// Connects to `addr` and remembers it: if the link is lost later,
+    /// the next request reconnects on its own.
     pub async fn connect(&mut self, addr: &str) -> ConnectResult<()> {
-        *self.stream.lock().unwrap() = Some(TcpStream::connect(addr)?);
-        Ok(())
+        self.addr = Some(addr.into());
+        let res = time::timeout(self.timeout, self.open(addr))
+            .await
+            .unwrap_or(Err(ConnectError::Timeout(self.timeout)));
+        let mut guard = self.connection.lock().await;
+        match res {
+            Ok(connection) => {
+                *guard = Some(connection);
+                self.set_health(ConnectionState::Connected);
+                Ok(())
+            }
+            Err(e) => {
+                *guard = None;
+                self.set_down(&e);
+                Err(e)
+            }
+        }
     }
 
-    async fn check_connection(&self) -> ConnectResult<()> {
-        if self.stream.lock().unwrap().is_none() {
-            return Err(ConnectError::Io(std::io::Error::new(
+    /// Tries to connect to the remembered address, pausing between attempts
+    /// as `backoff` says, until `deadline`. A device refusing the handshake
+    /// is not retried.
+    async fn reconnect(&self, deadline: Instant) -> ConnectResult<Connection> {
+        let addr = self.addr.as_deref().ok_or_else(|| {
+            ConnectError::Io(std::io::Error::new(
                 ErrorKind::NotConnected,
                 format!("no connection established to {}", self.name),
-            )));
+            ))
+        })?;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.set_health(ConnectionState::Reconnecting { attempt });
+            let err = match time::timeout_at(deadline, self.open(addr)).await {
+                Ok(Ok(connection)) => {
+                    self.set_health(ConnectionState::Connected);
+                    return Ok(connection);
+                }
+                Ok(Err(e)) => e,
+                Err(_) => ConnectError::Timeout(self.timeout),
+            };
+            let delay = self.backoff.delay(attempt - 1);
+            if attempt >= self.backoff.attempts
+                || matches!(err, ConnectError::Remote { .. })
+                || Instant::now() + delay >= deadline
+            {
+                self.set_down(&err);
+                return Err(err);
+            }
+            time::sleep(delay).await;
+        }
+    }
+
+    fn set_health(&self, state: ConnectionState) {
+        *self.health.lock().unwrap() = state;
+    }
+
+    fn set_down(&self, error: &ConnectError) {
+        self.set_health(ConnectionState::Down {
+            error: error.to_string(),
+            at: SystemTime::now(),
+        });
+    }
+
+    async fn open(&self, addr: &str) -> ConnectResult<Connection> {
+        let tcp = TcpStream::connect(addr).await?;
+        let stream: Box<dyn Stream> = match &self.tls {
+            Some(config) => {
+                let connector = TlsConnector::from(config.clone());
+                Box::new(connector.connect(server_name(addr)?, tcp).await?)
+            }
+            None => Box::new(tcp),
+        };
+        let mut connection = Connection {
+            stream,
+            decoder: FrameDecoder::default(),
+        };
+        if let Some(secret) = &self.secret {
+            let nonce = match connection.request(&ProtocolCommand::Challenge).await? {
+                ProtocolResponse::Challenge { nonce } => nonce,
+                other => return Err(unexpected(other)),
+            };
+            let signature = auth::sign(secret.as_bytes(), &nonce);
+            match connection
+                .request(&ProtocolCommand::Auth(signature))
+                .await?
+            {
+                ProtocolResponse::Ok => (),
+                other => return Err(unexpected(other)),
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Sends `command`, retrying as `policy` says. However many attempts it
+    /// takes, the breaker counts the request as a single failure.
+    async fn request(
+        &self,
+        command: ProtocolCommand,
+        policy: &RetryPolicy,
+    ) -> ConnectResult<ProtocolResponse> {
+        self.breaker
+            .call(policy.run(|| self.exchange(&command)))
+            .await
+    }
+
+    async fn exchange(&self, command: &ProtocolCommand) -> ConnectResult<ProtocolResponse> {
+        let deadline = Instant::now() + self.timeout;
+        let mut guard = self.connection.lock().await;
+        // the connection is only put back once the exchange completes, so a
+        // request abandoned halfway does not leave a stale answer behind
+        let (mut connection, reused) = match guard.take() {
+            Some(connection) => (connection, true),
+            None => (self.reconnect(deadline).await?, false),
+        };
+        let mut res = time::timeout_at(deadline, connection.request(command))
+            .await
+            .unwrap_or(Err(ConnectError::Timeout(self.timeout)));
+        // an idle connection breaks when the socket restarts, and the
+        // restarted socket never saw the request: send it once more, unless
+        // it was a switch the socket may have applied before the break
+        if reused && is_idempotent(command) && matches!(res, Err(ConnectError::Io(_))) {
+            connection = self.reconnect(deadline).await?;
+            res = time::timeout_at(deadline, connection.request(command))
+                .await
+                .unwrap_or(Err(ConnectError::Timeout(self.timeout)));
+        }
+        match res {
+            Ok(ProtocolResponse::Error { code, message }) => {
+                *guard = Some(connection);
+                Err(ConnectError::Remote { code, message })
+            }
+            Err(e) => {
+                // the stream is out of sync after a failed exchange,
+                // the next request starts over with a fresh one
+                self.set_down(&e);
+                Err(e)
+            }
+            ok => {
+                *guard = Some(connection);
+                ok
+            }
         }
-        Ok(())
     }
 
     async fn get_status(&self) -> ConnectResult<SocketState> {
-        self.check_connection().await?;
-        self.stream
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .write_all(ProtocolCommand::Status.to_string().as_bytes())?;
-
-        let mut buf = vec![0; 16];
-        let n = self
-            .stream
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .read(&mut buf)?;
-        let s = str::from_utf8(&buf[..n]).unwrap();
-        match Regex::new(r"is on \((\d+)W\)\r\n").unwrap().captures(s) {
-            Some(group) => Ok(SocketState {
-                is_on: true,
-                power_consumption: group.get(1).unwrap().as_str().parse().unwrap(),
+        let response = self.request(ProtocolCommand::Status, &self.retry).await?;
+        match response {
+            ProtocolResponse::Status { on, watts } => Ok(SocketState {
+                is_on: on,
+                power_consumption: watts,
             }),
-            None => Ok(SocketState::default()),
+            other => Err(ConnectError::UnexpectedResponse(other.to_string())),
+        }
+    }
+
+    /// Sends a command that is answered with a plain acknowledgement.
+    async fn command(&self, command: ProtocolCommand, policy: &RetryPolicy) -> ConnectResult<()> {
+        match self.request(command, policy).await? {
+            ProtocolResponse::Ok => Ok(()),
+            other => Err(ConnectError::UnexpectedResponse(other.to_string())),
         }
     }
 
@@ -79,13 +303,58 @@ impl SmartSocket {
         self::Switcher::switch(self).await
     }
 
+    pub async fn turn_on(&mut self) -> ConnectResult<()> {
+        self.command(ProtocolCommand::On, &self.retry).await
+    }
+
+    pub async fn turn_off(&mut self) -> ConnectResult<()> {
+        self.command(ProtocolCommand::Off, &self.retry).await
+    }
+
     pub async fn is_on(&self) -> ConnectResult<bool> {
         self.get_status().await.map(|res| res.is_on)
     }
 
-    pub async fn get_consumed_power(&self) -> ConnectResult<usize> {
+    pub async fn get_consumed_power(&self) -> ConnectResult<f64> {
         self.get_status().await.map(|res| res.power_consumption)
     }
+
+    /// Energy consumed over the lifetime of the socket, in watt-hours.
+    pub async fn get_consumed_energy(&self) -> ConnectResult<f64> {
+        let response = self.request(ProtocolCommand::Energy, &self.retry).await?;
+        match response {
+            ProtocolResponse::Energy { wh } => Ok(wh),
+            other => Err(ConnectError::UnexpectedResponse(other.to_string())),
+        }
+    }
+}
+
+/// The name the socket's certificate has to be issued for: the host part of `addr`.
+fn server_name(addr: &str) -> ConnectResult<ServerName> {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(host)
+        .map_err(|e| ConnectError::Io(io::Error::new(ErrorKind::InvalidInput, e)))
+}
+
+/// Whether sending `command` twice leaves the socket as sending it once does.
+fn is_idempotent(command: &ProtocolCommand) -> bool {
+    matches!(
+        command,
+        ProtocolCommand::Status
+            | ProtocolCommand::Energy
+            | ProtocolCommand::On
+            | ProtocolCommand::Off
+    )
+}
+
+/// Maps an answer the caller did not expect to an error, keeping the
+/// device's own error if it sent one.
+fn unexpected(response: ProtocolResponse) -> ConnectError {
+    match response {
+        ProtocolResponse::Error { code, message } => ConnectError::Remote { code, message },
+        other => ConnectError::UnexpectedResponse(other.to_string()),
+    }
 }
 
 impl Device for SmartSocket {
@@ -100,37 +369,33 @@ impl Device for SmartSocket {
 #[async_trait::async_trait]
 impl Switcher for SmartSocket {
     async fn switch(&mut self) -> ConnectResult<()> {
-        self.check_connection().await?;
-        self.stream
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .write_all(ProtocolCommand::Switch.to_string().as_bytes())?;
-        let mut buf = vec![0; 4];
-        self.stream
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .read_exact(&mut buf)?;
-        Ok(())
+        self.command(ProtocolCommand::Switch, &RetryPolicy::never())
+            .await
     }
 }
 
 #[async_trait::async_trait]
-impl Summary for SmartSocket {
-    async fn summary(&self) -> String {
-        let power = self.get_consumed_power().await.unwrap();
-        format!(
+impl TrySummary for SmartSocket {
+    async fn try_summary(&self) -> ConnectResult<String> {
+        let status = self.get_status().await?;
+        Ok(format!(
             "{} ({}W)",
-            if self.is_on().await.unwrap() {
+            if status.is_on {
                 "turned on"
             } else {
                 "turned off"
             },
-            power,
-        )
+            status.power_consumption,
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Summary for SmartSocket {
+    async fn summary(&self) -> String {
+        self.try_summary()
+            .await
+            .unwrap_or_else(|e| format!("error: {}", e))
     }
 }
 
@@ -138,6 +403,10 @@ impl Summary for SmartSocket {
 mod tests {
     use std::{process::Command, thread::sleep, time::Duration};
 
+    use smart_socket::protocol::{FramedStream, ERR_AUTH_FAILED, ERR_UNAUTHENTICATED};
+    use smart_socket::receiver::SmartSocketServer;
+    use smart_socket::tls;
+
     use super::*;
 
     fn run_test<T>(test: T)
@@ -160,6 +429,7 @@ mod tests {
 
         test();
         cmd.kill().unwrap();
+        cmd.wait().unwrap();
     }
 
     #[test]
@@ -175,8 +445,339 @@ mod tests {
                 assert!(socket.is_on().await.unwrap());
                 socket.switch().await.unwrap();
                 assert!(!socket.is_on().await.unwrap());
+                assert!(socket.get_consumed_energy().await.unwrap() > 0.0);
+
+                socket.turn_on().await.unwrap();
+                socket.turn_on().await.unwrap();
+                assert!(socket.is_on().await.unwrap());
+                socket.turn_off().await.unwrap();
+                socket.turn_off().await.unwrap();
+                assert!(!socket.is_on().await.unwrap());
             });
             rt.shutdown_background();
         })
     }
+
+    #[test]
+    fn test_request_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut socket = SmartSocket::new("socket", "description");
+        socket.set_timeout(Duration::from_millis(100));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            socket.connect(&addr).await.unwrap();
+            match socket.is_on().await {
+                Err(ConnectError::Timeout(_)) => (),
+                other => panic!("expected timeout, got {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn test_authenticated_connection() {
+        let mut server = SmartSocketServer::bind("127.0.0.1:0").unwrap();
+        server.set_secret("secret");
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.serve());
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut socket = SmartSocket::new("socket", "description");
+            socket.connect(&addr).await.unwrap();
+            match socket.is_on().await {
+                Err(ConnectError::Remote { code, .. }) => assert_eq!(code, ERR_UNAUTHENTICATED),
+                other => panic!("expected authentication error, got {:?}", other),
+            }
+
+            socket.set_secret("guess");
+            match socket.connect(&addr).await {
+                Err(ConnectError::Remote { code, .. }) => assert_eq!(code, ERR_AUTH_FAILED),
+                other => panic!("expected authentication error, got {:?}", other),
+            }
+
+            socket.set_secret("secret");
+            socket.connect(&addr).await.unwrap();
+            socket.switch().await.unwrap();
+            assert!(socket.is_on().await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_tls_connection() {
+        let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+        let cert_pem = cert.serialize_pem().unwrap();
+        let key_pem = cert.serialize_private_key_pem();
+
+        let mut server = SmartSocketServer::bind("127.0.0.1:0").unwrap();
+        server
+            .set_tls(tls::server_config_from_pem(cert_pem.as_bytes(), key_pem.as_bytes()).unwrap());
+        let addr = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || server.serve());
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut socket = SmartSocket::new("socket", "description");
+            socket.set_tls(tls::client_config_from_pem(cert_pem.as_bytes()).unwrap());
+            socket.connect(&addr).await.unwrap();
+            socket.switch().await.unwrap();
+            assert!(socket.is_on().await.unwrap());
+
+            // a certificate from somebody else is rejected
+            let other = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+            let mut socket = SmartSocket::new("socket", "description");
+            socket.set_tls(
+                tls::client_config_from_pem(other.serialize_pem().unwrap().as_bytes()).unwrap(),
+            );
+            assert!(matches!(
+                socket.connect(&addr).await,
+                Err(ConnectError::Io(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_reconnect_after_restart() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // every connection answers a single status request and is closed,
+        // as if the socket process restarted in between
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = FramedStream::new(stream.unwrap());
+                stream.read_frame().unwrap();
+                stream
+                    .write_frame(&ProtocolResponse::Status {
+                        on: true,
+                        watts: 2.0,
+                    })
+                    .unwrap();
+            }
+        });
+
+        let mut socket = SmartSocket::new("socket", "description");
+        assert_eq!(socket.connection_state(), ConnectionState::Disconnected);
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            socket.connect(&addr).await.unwrap();
+            for _ in 0..3 {
+                assert!(socket.is_on().await.unwrap());
+                assert_eq!(socket.connection_state(), ConnectionState::Connected);
+            }
+        });
+    }
+
+    #[test]
+    fn test_switch_not_resent_after_lost_answer() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // the socket applies a switch and closes the connection before
+        // answering, as if it crashed right after toggling
+        let switches = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let _switches = switches.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = FramedStream::new(stream.unwrap());
+                while let Ok(Some(frame)) = stream.read_frame() {
+                    if frame == "switch" {
+                        _switches.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        break;
+                    }
+                    stream
+                        .write_frame(&ProtocolResponse::Status {
+                            on: true,
+                            watts: 2.0,
+                        })
+                        .unwrap();
+                }
+            }
+        });
+
+        let mut socket = SmartSocket::new("socket", "description");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            socket.connect(&addr).await.unwrap();
+            assert!(socket.is_on().await.unwrap());
+            assert!(matches!(socket.switch().await, Err(ConnectError::Io(_))));
+        });
+        assert_eq!(switches.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_status_across_restart() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = FramedStream::new(stream.unwrap());
+                stream.read_frame().unwrap();
+                stream
+                    .write_frame(&ProtocolResponse::Status {
+                        on: true,
+                        watts: 2.0,
+                    })
+                    .unwrap();
+            }
+        });
+
+        let mut socket = SmartSocket::new("socket", "description");
+        socket.set_retry_policy(RetryPolicy {
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(10),
+                attempts: 2,
+            },
+            ..RetryPolicy::default()
+        });
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            socket.connect(&addr).await.unwrap();
+            for _ in 0..3 {
+                assert!(socket.is_on().await.unwrap());
+            }
+        });
+    }
+
+    #[test]
+    fn test_reconnect_gives_up() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut socket = SmartSocket::new("socket", "description");
+        socket.set_backoff(Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(10),
+            attempts: 3,
+        });
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert!(socket.connect(&addr).await.is_err());
+            assert!(matches!(socket.is_on().await, Err(ConnectError::Io(_))));
+            match socket.connection_state() {
+                ConnectionState::Down { error, .. } => assert!(error.contains("IO error")),
+                other => panic!("expected down, got {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn test_reconnect_within_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut socket = SmartSocket::new("socket", "description");
+        socket.set_timeout(Duration::from_millis(200));
+        socket.set_backoff(Backoff {
+            initial: Duration::from_millis(50),
+            max: Duration::from_millis(50),
+            attempts: 100,
+        });
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert!(socket.connect(&addr).await.is_err());
+            let started = std::time::Instant::now();
+            assert!(socket.is_on().await.is_err());
+            assert!(started.elapsed() < Duration::from_millis(400));
+        });
+    }
+
+    #[test]
+    fn test_breaker_stops_calling_unreachable_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut socket = SmartSocket::new("socket", "description");
+        socket.set_backoff(Backoff {
+            attempts: 1,
+            ..Backoff::default()
+        });
+        socket.set_breaker(Breaker::new(2, Duration::from_secs(60)));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert!(socket.connect(&addr).await.is_err());
+            assert!(matches!(socket.is_on().await, Err(ConnectError::Io(_))));
+            assert!(matches!(socket.is_on().await, Err(ConnectError::Io(_))));
+            assert!(matches!(
+                socket.is_on().await,
+                Err(ConnectError::CircuitOpen(_))
+            ));
+            assert!(matches!(socket.breaker_state(), BreakerState::Open { .. }));
+        });
+    }
+
+    #[test]
+    fn test_breaker_counts_retried_request_once() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut socket = SmartSocket::new("socket", "description");
+        let backoff = Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+            attempts: 3,
+        };
+        socket.set_backoff(Backoff {
+            attempts: 1,
+            ..backoff
+        });
+        socket.set_retry_policy(RetryPolicy {
+            backoff,
+            ..RetryPolicy::default()
+        });
+        socket.set_breaker(Breaker::new(3, Duration::from_secs(60)));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert!(socket.connect(&addr).await.is_err());
+            assert!(matches!(socket.is_on().await, Err(ConnectError::Io(_))));
+            assert_eq!(socket.breaker_state(), BreakerState::Closed { failures: 1 });
+        });
+    }
+
+    #[test]
+    fn test_summary_of_unconnected_socket() {
+        let socket = SmartSocket::new("socket", "description");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert!(socket.try_summary().await.is_err());
+            assert_eq!(
+                socket.summary().await,
+                "error: IO error: no connection established to socket"
+            );
+        });
+    }
+
+    #[test]
+    fn test_remote_error() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = FramedStream::new(stream);
+            stream.read_frame().unwrap();
+            stream
+                .write_frame(&ProtocolResponse::Error {
+                    code: 500,
+                    message: "relay is stuck".into(),
+                })
+                .unwrap();
+        });
+
+        let mut socket = SmartSocket::new("socket", "description");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            socket.connect(&addr).await.unwrap();
+            match socket.is_on().await {
+                Err(ConnectError::Remote { code, message }) => {
+                    assert_eq!(code, 500);
+                    assert_eq!(message, "relay is stuck");
+                }
+                other => panic!("expected remote error, got {:?}", other),
+            }
+        });
+    }
 }
@@ -1,50 +1,109 @@
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use thermometer::datagram::{MeasurementKind, TemperatureUnit, Unit};
+
+use crate::connection::ConnectResult;
+use crate::devices::sensor::Sensor;
+use crate::history::{Point, Stats};
+use crate::receiver::Reading;
 use crate::{devices::device::Device, receiver::Receiver};
 
-use super::device::Summary;
+use super::device::{Summary, TrySummary};
+
+/// A thermometer sends a reading every 100ms, one that has not been
+/// heard of for this long is most likely gone.
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(60);
 
+/// A sensor that only measures temperature.
 #[derive(Debug)]
 pub struct Thermometer {
-    name: String,
-    description: String,
-    receiver: Arc<Mutex<Option<Receiver>>>,
+    sensor: Sensor,
 }
 
 impl Thermometer {
     pub fn new(name: &str, description: &str) -> Self {
         Self {
-            name: name.into(),
-            description: description.into(),
-            receiver: Arc::new(Mutex::new(None)),
+            sensor: Sensor::new(name, description),
         }
     }
 
     pub fn add_receiver(&mut self, receiver: Arc<Mutex<Option<Receiver>>>) {
-        self.receiver = receiver;
+        self.sensor.add_receiver(receiver);
+    }
+
+    pub fn set_stale_after(&mut self, stale_after: Duration) {
+        self.sensor.set_stale_after(stale_after);
+    }
+
+    /// Sets the unit the summary shows temperatures in, °C by default.
+    pub fn set_units(&mut self, units: TemperatureUnit) {
+        self.sensor.set_units(units);
+    }
+
+    /// The last reading in °C, however old it is.
+    pub fn get_reading(&self) -> Option<Reading> {
+        self.sensor.get_measurement(MeasurementKind::Temperature)
     }
 
-    pub fn get_temperature(&self) -> f64 {
-        if let Some(receiver) = &*self.receiver.lock().unwrap() {
-            return receiver.get_data(&self.name).unwrap_or(0.0);
+    pub fn is_stale(&self, reading: &Reading) -> bool {
+        self.sensor.is_stale(reading)
+    }
+
+    /// Temperatures in °C of the last `window`, oldest first.
+    pub fn history(&self, window: Duration) -> Vec<Point> {
+        self.sensor.history(MeasurementKind::Temperature, window)
+    }
+
+    /// Min, max, average and trend in degrees per hour over the last `window`.
+    pub fn stats(&self, window: Duration) -> Option<Stats> {
+        self.sensor.stats(MeasurementKind::Temperature, window)
+    }
+
+    /// The current temperature, `None` if nothing or nothing recent arrived.
+    pub fn get_temperature(&self) -> Option<f64> {
+        self.sensor.get_value(MeasurementKind::Temperature)
+    }
+
+    /// The current temperature in `units`, `None` if nothing or nothing
+    /// recent arrived.
+    pub fn get_temperature_in(&self, units: TemperatureUnit) -> Option<f64> {
+        self.get_temperature()
+            .and_then(|value| Unit::Celsius.convert(value, units.into()))
+    }
+
+    fn describe(&self) -> String {
+        self.describe_in(self.sensor.units())
+    }
+
+    pub(crate) fn describe_in(&self, units: TemperatureUnit) -> String {
+        match self.get_reading() {
+            None => "no data".into(),
+            Some(reading) => self.sensor.describe_reading(reading, units),
         }
-        0.0
     }
 }
 
 impl Device for Thermometer {
     fn get_name(&self) -> &str {
-        &self.name
+        self.sensor.get_name()
     }
     fn get_description(&self) -> &str {
-        &self.description
+        self.sensor.get_description()
+    }
+}
+
+#[async_trait::async_trait]
+impl TrySummary for Thermometer {
+    async fn try_summary(&self) -> ConnectResult<String> {
+        Ok(self.describe())
     }
 }
 
 #[async_trait::async_trait]
 impl Summary for Thermometer {
     async fn summary(&self) -> String {
-        format!("{}°C", self.get_temperature())
+        self.describe()
     }
 }
 
@@ -57,7 +116,46 @@ mod tests {
     #[test]
     fn test_get_temperature() {
         let thermometer = Thermometer::new("t", "description");
-        assert!(thermometer.get_temperature() < f64::EPSILON);
+        assert_eq!(thermometer.get_temperature(), None);
+        assert_eq!(thermometer.describe(), "no data");
+    }
+
+    #[test]
+    fn test_stale_reading() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let receiver = Receiver::new("127.0.0.1:0").await.unwrap();
+            std::net::UdpSocket::bind("127.0.0.1:0")
+                .unwrap()
+                .send_to(b"t:\t-1.5", receiver.local_addr())
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let mut thermometer = Thermometer::new("t", "");
+            thermometer.add_receiver(Arc::new(Mutex::new(Some(receiver))));
+            assert_eq!(thermometer.get_temperature(), Some(-1.5));
+            assert_eq!(thermometer.describe(), "-1.5°C");
+            assert_eq!(
+                thermometer.get_temperature_in(TemperatureUnit::Fahrenheit),
+                Some(29.3)
+            );
+            thermometer.set_units(TemperatureUnit::Kelvin);
+            assert_eq!(thermometer.describe(), "271.65 K");
+            assert_eq!(
+                thermometer.describe_in(TemperatureUnit::Fahrenheit),
+                "29.3°F"
+            );
+            assert_eq!(thermometer.history(Duration::from_secs(60)).len(), 1);
+            assert_eq!(
+                thermometer.stats(Duration::from_secs(60)).unwrap().avg,
+                -1.5
+            );
+
+            thermometer.set_stale_after(Duration::ZERO);
+            assert_eq!(thermometer.get_temperature(), None);
+            assert_eq!(thermometer.get_reading().unwrap().value, -1.5);
+            assert_eq!(thermometer.describe(), "stale (last 0 s ago)");
+        });
     }
 
     fn run_test<T>(test: T)
@@ -83,6 +181,7 @@ mod tests {
 
         test();
         cmd.kill().unwrap();
+        cmd.wait().unwrap();
     }
 
     #[test]
@@ -95,7 +194,7 @@ mod tests {
                 thermometer.add_receiver(Arc::new(Mutex::new(Some(receiver))));
 
                 sleep(Duration::from_millis(200));
-                assert!((thermometer.get_temperature() - 25.0).abs() < f64::EPSILON);
+                assert_eq!(thermometer.get_temperature(), Some(25.0));
             });
             rt.shutdown_background();
         })
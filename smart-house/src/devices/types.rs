@@ -1,15 +1,34 @@
+use thermometer::datagram::TemperatureUnit;
+
+use crate::connection::ConnectResult;
 use crate::devices::device::Device;
+use crate::devices::sensor::Sensor;
 use crate::devices::smartsocket::SmartSocket;
 use crate::devices::thermometer::Thermometer;
 
-use super::device::Summary;
+use super::device::{Summary, TrySummary};
 
 pub struct DevicesIter {}
 
+// a house holds a handful of devices, boxing them is not worth it
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum DeviceType {
     Thermometer(Thermometer),
     SmartSocket(SmartSocket),
+    Sensor(Sensor),
+}
+
+impl DeviceType {
+    /// Like `try_summary`, but with temperatures in `units` whatever the
+    /// device is set to.
+    pub async fn try_summary_in(&self, units: TemperatureUnit) -> ConnectResult<String> {
+        match self {
+            DeviceType::Thermometer(t) => Ok(t.describe_in(units)),
+            DeviceType::SmartSocket(s) => s.try_summary().await,
+            DeviceType::Sensor(s) => Ok(s.describe_in(units)),
+        }
+    }
 }
 
 impl Device for DeviceType {
@@ -17,6 +36,7 @@ impl Device for DeviceType {
         match self {
             DeviceType::Thermometer(t) => t.get_name(),
             DeviceType::SmartSocket(s) => s.get_name(),
+            DeviceType::Sensor(s) => s.get_name(),
         }
     }
 
@@ -24,6 +44,7 @@ impl Device for DeviceType {
         match self {
             DeviceType::Thermometer(t) => t.get_description(),
             DeviceType::SmartSocket(s) => s.get_description(),
+            DeviceType::Sensor(s) => s.get_description(),
         }
     }
 }
@@ -34,6 +55,18 @@ impl Summary for DeviceType {
         match self {
             DeviceType::Thermometer(t) => t.summary().await,
             DeviceType::SmartSocket(s) => s.summary().await,
+            DeviceType::Sensor(s) => s.summary().await,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TrySummary for DeviceType {
+    async fn try_summary(&self) -> ConnectResult<String> {
+        match self {
+            DeviceType::Thermometer(t) => t.try_summary().await,
+            DeviceType::SmartSocket(s) => s.try_summary().await,
+            DeviceType::Sensor(s) => s.try_summary().await,
         }
     }
 }
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::str;
+use std::time::Duration;
+
+use discovery::{Announcement, DeviceKind, PROBE};
+use tokio::net::UdpSocket;
+use tokio::time;
+
+use crate::connection::ConnectResult;
+use crate::devices::smartsocket::SmartSocket;
+use crate::devices::thermometer::Thermometer;
+use crate::devices::types::DeviceType;
+
+pub const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A device that answered a discovery probe.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Candidate {
+    pub kind: DeviceKind,
+    pub name: String,
+    pub address: SocketAddr,
+}
+
+impl Candidate {
+    /// Builds a device to be added to a `Room`. Smart sockets come back
+    /// already connected to the announced address. Thermometers are not
+    /// linked to any receiver: they report no data until added to a room
+    /// and passed to `Room::connect_device_to_receiver`.
+    pub async fn into_device(self) -> ConnectResult<DeviceType> {
+        match self.kind {
+            DeviceKind::SmartSocket => {
+                let mut socket = SmartSocket::new(&self.name, "");
+                socket.connect(&self.address.to_string()).await?;
+                Ok(DeviceType::SmartSocket(socket))
+            }
+            DeviceKind::Thermometer => {
+                Ok(DeviceType::Thermometer(Thermometer::new(&self.name, "")))
+            }
+        }
+    }
+}
+
+/// Sends a probe to `target`, usually `discovery::BROADCAST_ADDRESS`,
+/// and collects the answers arriving within `wait`.
+pub async fn discover(target: &str, wait: Duration) -> ConnectResult<Vec<Candidate>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.set_broadcast(true)?;
+    socket.send_to(PROBE.as_bytes(), target).await?;
+
+    let mut found = HashSet::new();
+    let mut buf = [0; 512];
+    let deadline = time::Instant::now() + wait;
+    while let Ok(res) = time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (n, from) = res?;
+        let announcement = match str::from_utf8(&buf[..n])
+            .ok()
+            .and_then(|s| s.parse::<Announcement>().ok())
+        {
+            Some(announcement) => announcement,
+            None => {
+                println!("ignoring malformed discovery answer from {}", from);
+                continue;
+            }
+        };
+
+        let mut address = announcement.address;
+        // devices listening on all interfaces are reachable where they answered from
+        if address.ip().is_unspecified() {
+            address.set_ip(from.ip());
+        }
+        found.insert(Candidate {
+            kind: announcement.kind,
+            name: announcement.name,
+            address,
+        });
+    }
+    Ok(found.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use discovery::Responder;
+    use smart_socket::receiver::SmartSocketServer;
+
+    use super::*;
+    use crate::devices::device::Device;
+
+    #[test]
+    fn test_discover_socket() {
+        let mut server = SmartSocketServer::bind("127.0.0.1:0").unwrap();
+        server
+            .enable_discovery("127.0.0.1:0", "socket near the bed")
+            .unwrap();
+        let discovery_addr = server.discovery_addr().unwrap().to_string();
+        std::thread::spawn(move || server.serve());
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut candidates = discover(&discovery_addr, Duration::from_millis(300))
+                .await
+                .unwrap();
+            assert_eq!(candidates.len(), 1);
+            match candidates.pop().unwrap().into_device().await.unwrap() {
+                DeviceType::SmartSocket(mut socket) => {
+                    assert_eq!(socket.get_name(), "socket near the bed");
+                    socket.switch().await.unwrap();
+                    assert!(socket.is_on().await.unwrap());
+                }
+                other => panic!("expected a socket, got {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn test_discover_fills_in_unspecified_address() {
+        let responder = Responder::spawn(
+            "127.0.0.1:0",
+            Announcement {
+                kind: DeviceKind::Thermometer,
+                name: "thermometer on the wall".into(),
+                address: "0.0.0.0:11601".parse().unwrap(),
+            },
+        )
+        .unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let candidates = discover(
+                &responder.local_addr().to_string(),
+                Duration::from_millis(300),
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                candidates,
+                vec![Candidate {
+                    kind: DeviceKind::Thermometer,
+                    name: "thermometer on the wall".into(),
+                    address: "127.0.0.1:11601".parse().unwrap(),
+                }]
+            );
+        });
+    }
+}
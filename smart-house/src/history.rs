@@ -0,0 +1,231 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Readings are averaged over buckets this wide, the 10 or so a thermometer
+/// sends every second are too many to keep.
+pub const DEFAULT_BUCKET_WIDTH: Duration = Duration::from_secs(10);
+/// With the default bucket width, a day worth of history.
+pub const DEFAULT_CAPACITY: usize = 8640;
+
+/// Readings that arrived within one bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// Start of the bucket.
+    pub at: SystemTime,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub samples: u64,
+}
+
+/// Summary of the readings within a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    /// Change per hour by a least squares fit of the bucket averages, `0.0`
+    /// with fewer than two buckets.
+    pub trend: f64,
+    pub samples: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    start: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    samples: u64,
+}
+
+impl Bucket {
+    fn avg(&self) -> f64 {
+        self.sum / self.samples as f64
+    }
+}
+
+/// Bounded time series of a single sensor, oldest buckets are dropped first.
+#[derive(Debug, Clone)]
+pub struct History {
+    bucket_width: Duration,
+    capacity: usize,
+    buckets: VecDeque<Bucket>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUCKET_WIDTH, DEFAULT_CAPACITY)
+    }
+}
+
+impl History {
+    pub fn new(bucket_width: Duration, capacity: usize) -> Self {
+        assert!(!bucket_width.is_zero(), "bucket width must not be zero");
+        Self {
+            bucket_width,
+            capacity,
+            buckets: VecDeque::new(),
+        }
+    }
+
+    /// Readings arriving out of order go to the bucket they belong to, those
+    /// older than the history are dropped.
+    pub fn record(&mut self, value: f64, at: SystemTime) {
+        let start = self.bucket_start(at);
+        let pos = self.buckets.partition_point(|b| b.start < start);
+        let full = self.buckets.len() == self.capacity;
+        match self.buckets.get_mut(pos) {
+            Some(bucket) if bucket.start == start => {
+                bucket.min = bucket.min.min(value);
+                bucket.max = bucket.max.max(value);
+                bucket.sum += value;
+                bucket.samples += 1;
+            }
+            _ if pos == 0 && full => {}
+            _ => {
+                self.buckets.insert(
+                    pos,
+                    Bucket {
+                        start,
+                        min: value,
+                        max: value,
+                        sum: value,
+                        samples: 1,
+                    },
+                );
+                if self.buckets.len() > self.capacity {
+                    self.buckets.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Buckets starting at `since` or later, oldest first.
+    pub fn points(&self, since: SystemTime) -> Vec<Point> {
+        self.window(since)
+            .map(|b| Point {
+                at: UNIX_EPOCH + Duration::from_millis(b.start),
+                min: b.min,
+                max: b.max,
+                avg: b.avg(),
+                samples: b.samples,
+            })
+            .collect()
+    }
+
+    /// `None` if nothing arrived since `since`.
+    pub fn stats(&self, since: SystemTime) -> Option<Stats> {
+        let mut buckets = self.window(since).peekable();
+        let origin = buckets.peek()?.start;
+
+        let (mut min, mut max, mut sum, mut samples) = (f64::INFINITY, f64::NEG_INFINITY, 0.0, 0);
+        let (mut n, mut sum_x, mut sum_y, mut sum_xx, mut sum_xy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for b in buckets {
+            min = min.min(b.min);
+            max = max.max(b.max);
+            sum += b.sum;
+            samples += b.samples;
+
+            let x = (b.start - origin) as f64 / 3_600_000.0;
+            let y = b.avg();
+            n += 1.0;
+            sum_x += x;
+            sum_y += y;
+            sum_xx += x * x;
+            sum_xy += x * y;
+        }
+        let denominator = n * sum_xx - sum_x * sum_x;
+        let trend = if denominator > 0.0 {
+            (n * sum_xy - sum_x * sum_y) / denominator
+        } else {
+            0.0
+        };
+        Some(Stats {
+            min,
+            max,
+            avg: sum / samples as f64,
+            trend,
+            samples,
+        })
+    }
+
+    fn window(&self, since: SystemTime) -> impl Iterator<Item = &Bucket> {
+        let start = self.bucket_start(since);
+        let pos = self.buckets.partition_point(|b| b.start < start);
+        self.buckets.range(pos..)
+    }
+
+    /// Milliseconds since the epoch, rounded down to the bucket width.
+    fn bucket_start(&self, at: SystemTime) -> u64 {
+        let millis = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let width = self.bucket_width.as_millis().max(1) as u64;
+        millis - millis % width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_record_into_buckets() {
+        let mut history = History::new(Duration::from_secs(10), 3);
+        history.record(1.0, at(100));
+        history.record(3.0, at(109));
+        history.record(5.0, at(125));
+        // late, but still in the history
+        history.record(2.0, at(111));
+
+        let points = history.points(at(0));
+        assert_eq!(points.len(), 3);
+        assert_eq!(
+            points[0],
+            Point {
+                at: at(100),
+                min: 1.0,
+                max: 3.0,
+                avg: 2.0,
+                samples: 2,
+            }
+        );
+        assert_eq!(points[1].at, at(110));
+        assert_eq!(points[2].at, at(120));
+
+        // the oldest bucket makes room, and nothing older gets in
+        history.record(6.0, at(130));
+        history.record(0.0, at(90));
+        let points = history.points(at(0));
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].at, at(110));
+
+        assert_eq!(history.points(at(125)).len(), 2);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut history = History::new(Duration::from_secs(60), 100);
+        assert_eq!(history.stats(at(0)), None);
+
+        // warms up by a degree every 10 minutes
+        for minute in 0..=30 {
+            history.record(20.0 + minute as f64 / 10.0, at(minute * 60));
+        }
+        let stats = history.stats(at(0)).unwrap();
+        assert_eq!(stats.min, 20.0);
+        assert_eq!(stats.max, 23.0);
+        assert!((stats.avg - 21.5).abs() < 1e-9);
+        assert!((stats.trend - 6.0).abs() < 1e-9);
+        assert_eq!(stats.samples, 31);
+
+        let stats = history.stats(at(30 * 60)).unwrap();
+        assert_eq!((stats.min, stats.trend, stats.samples), (23.0, 0.0, 1));
+    }
+}
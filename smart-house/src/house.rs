@@ -1,16 +1,28 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
-use crate::devices::device::{Device, Summary};
+use futures::future::join_all;
+use thermometer::datagram::TemperatureUnit;
+use tokio::time::{self, Instant};
+
+use crate::connection::ConnectError;
+use crate::devices::device::{Device, Summary, TrySummary};
+use crate::devices::types::DeviceType;
 use crate::errors::HouseUpdateErr;
 use crate::formatter::{ItemType, PlainTextFormatter, ReportFormatter};
 use crate::report::HouseReport;
 use crate::room::Room;
 
+pub const DEFAULT_DEVICE_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_REPORT_DEADLINE: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub struct House {
     #[allow(dead_code)]
     name: String,
     rooms: HashMap<String, Room>,
+    device_timeout: Duration,
+    report_deadline: Duration,
 }
 
 impl House {
@@ -18,11 +30,23 @@ impl House {
         Self {
             name: name.into(),
             rooms: HashMap::new(),
+            device_timeout: DEFAULT_DEVICE_TIMEOUT,
+            report_deadline: DEFAULT_REPORT_DEADLINE,
         }
     }
 
+    /// Sets how long a report waits for a single device.
+    pub fn set_device_timeout(&mut self, timeout: Duration) {
+        self.device_timeout = timeout;
+    }
+
+    /// Sets how long a report waits for all the devices together.
+    pub fn set_report_deadline(&mut self, deadline: Duration) {
+        self.report_deadline = deadline;
+    }
+
     pub fn add_room(&mut self, name: &str) -> Result<(), HouseUpdateErr> {
-        if self.rooms.get(name).is_none() {
+        if !self.rooms.contains_key(name) {
             self.rooms.insert(name.to_owned(), Room::new(name));
             return Ok(());
         }
@@ -30,7 +54,7 @@ impl House {
     }
 
     pub fn remove_room(&mut self, name: &str) -> Result<(), HouseUpdateErr> {
-        if self.rooms.get(name).is_some() {
+        if self.rooms.contains_key(name) {
             self.rooms.remove(name);
             return Ok(());
         }
@@ -53,16 +77,64 @@ impl House {
         self.rooms.get_mut(name)
     }
 
+    /// Asks all the devices at once. A device that does not answer in time
+    /// is reported as such instead of holding up the rest.
     pub async fn summary_fmt(&self, fmt: Box<dyn ReportFormatter + Send>) -> String {
+        self.report(fmt, None).await
+    }
+
+    /// Like `summary_fmt`, but with all the temperatures in `units`.
+    pub async fn summary_fmt_in(
+        &self,
+        fmt: Box<dyn ReportFormatter + Send>,
+        units: TemperatureUnit,
+    ) -> String {
+        self.report(fmt, Some(units)).await
+    }
+
+    async fn report(
+        &self,
+        fmt: Box<dyn ReportFormatter + Send>,
+        units: Option<TemperatureUnit>,
+    ) -> String {
+        let devices: Vec<_> = self
+            .get_rooms()
+            .flat_map(|room| room.get_devices().map(move |device| (room, device)))
+            .collect();
+
+        let started = Instant::now();
+        let deadline = started + self.report_deadline;
+        let statuses = join_all(devices.iter().map(|(_, device)| async move {
+            let limit = deadline.min(started + self.device_timeout);
+            let summary = async {
+                match units {
+                    Some(units) => device.try_summary_in(units).await,
+                    None => device.try_summary().await,
+                }
+            };
+            time::timeout_at(limit, summary)
+                .await
+                .unwrap_or_else(|_| Err(ConnectError::Timeout(limit - started)))
+        }))
+        .await;
+
         let mut report: Vec<ItemType> = Vec::new();
-        for room in self.get_rooms() {
-            for device in room.get_devices() {
-                report.push(ItemType::NewObject());
-                report.push(ItemType::Str("room".into(), room.get_name().into()));
-                report.push(ItemType::Str("device".into(), device.get_name().into()));
-                report.push(ItemType::Str("summary".into(), device.summary().await));
-                report.push(ItemType::EndObject());
+        for ((room, device), status) in devices.into_iter().zip(statuses) {
+            report.push(ItemType::NewObject());
+            report.push(ItemType::Str("room".into(), room.get_name().into()));
+            report.push(ItemType::Str("device".into(), device.get_name().into()));
+            // one unreachable device must not spoil the whole report
+            match status {
+                Ok(summary) => report.push(ItemType::Str("summary".into(), summary)),
+                Err(e) => report.push(ItemType::Str("error".into(), e.to_string())),
+            }
+            if let DeviceType::SmartSocket(socket) = device {
+                report.push(ItemType::Str(
+                    "connection".into(),
+                    socket.connection_state().to_string(),
+                ));
             }
+            report.push(ItemType::EndObject());
         }
         HouseReport::new(report, fmt).summary()
     }
@@ -78,13 +150,13 @@ impl Summary for House {
 #[cfg(test)]
 mod tests {
     use std::process::Command;
+    use std::sync::{Arc, Mutex};
     use std::thread::sleep;
     use std::time::Duration;
 
     use super::*;
     use crate::devices::smartsocket::SmartSocket;
     use crate::devices::thermometer::Thermometer;
-    use crate::devices::types::DeviceType;
 
     #[test]
     fn test_add_remove_room() {
@@ -151,6 +223,85 @@ mod tests {
         assert_eq!(device.get_name(), socket);
     }
 
+    #[test]
+    fn test_report_unconnected_socket() {
+        let mut house = House::new("home");
+        house.add_room("living room").unwrap();
+        let room = house.get_room_mut("living room").unwrap();
+        room.add_device(DeviceType::SmartSocket(SmartSocket::new("socket", "")))
+            .unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let summary = rt.block_on(house.summary());
+        assert_eq!(
+            summary,
+            "room: living room, device: socket, error: IO error: no connection established to socket, connection: disconnected\n"
+        );
+    }
+
+    #[test]
+    fn test_report_does_not_wait_for_silent_devices() {
+        // accepts connections and never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut house = House::new("home");
+        house.set_device_timeout(Duration::from_millis(200));
+        house.add_room("living room").unwrap();
+        house.add_room("kitchen").unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            for room in house.get_rooms_mut() {
+                let mut socket = SmartSocket::new("socket", "");
+                socket.connect(&addr).await.unwrap();
+                room.add_device(DeviceType::SmartSocket(socket)).unwrap();
+            }
+
+            let started = std::time::Instant::now();
+            let summary = house.summary().await;
+            assert!(started.elapsed() < Duration::from_millis(400));
+            assert_eq!(
+                summary.matches("error: no response within 200ms").count(),
+                2
+            );
+        });
+        drop(listener);
+    }
+
+    #[test]
+    fn test_report_in_units() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let receiver = crate::receiver::Receiver::new("127.0.0.1:0").await.unwrap();
+            std::net::UdpSocket::bind("127.0.0.1:0")
+                .unwrap()
+                .send_to(b"balcony:\t-10", receiver.local_addr())
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let mut thermometer = Thermometer::new("balcony", "");
+            thermometer.add_receiver(Arc::new(Mutex::new(Some(receiver))));
+            thermometer.set_units(TemperatureUnit::Kelvin);
+            let mut house = House::new("home");
+            house.add_room("outside").unwrap();
+            let room = house.get_room_mut("outside").unwrap();
+            room.add_device(DeviceType::Thermometer(thermometer))
+                .unwrap();
+
+            assert_eq!(
+                house.summary().await,
+                "room: outside, device: balcony, summary: 263.15 K\n"
+            );
+            assert_eq!(
+                house
+                    .summary_fmt_in(Box::new(PlainTextFormatter {}), TemperatureUnit::Fahrenheit)
+                    .await,
+                "room: outside, device: balcony, summary: 14°F\n"
+            );
+        });
+    }
+
     fn run_socket_test<T>(test: T)
     where
         T: FnOnce(),
@@ -171,6 +322,7 @@ mod tests {
 
         test();
         cmd.kill().unwrap();
+        cmd.wait().unwrap();
     }
 
     fn run_thermometer_test<T>(test: T)
@@ -196,6 +348,7 @@ mod tests {
 
         test();
         cmd.kill().unwrap();
+        cmd.wait().unwrap();
     }
 
     #[test]
@@ -227,8 +380,8 @@ mod tests {
 
                     let summary = house.summary().await;
                     assert!(
-                        summary == "room: living room, device: socket near the bed, summary: turned on (2W)\nroom: living room, device: thermometer on the wall, summary: 23°C\n"
-                        || summary == "room: living room, device: thermometer on the wall, summary: 23°C\nroom: living room, device: socket near the bed, summary: turned on (2W)\n"
+                        summary == "room: living room, device: socket near the bed, summary: turned on (2W), connection: connected\nroom: living room, device: thermometer on the wall, summary: 23°C\n"
+                        || summary == "room: living room, device: thermometer on the wall, summary: 23°C\nroom: living room, device: socket near the bed, summary: turned on (2W), connection: connected\n"
                     );
                 });
                 rt.shutdown_background();
@@ -1,8 +1,11 @@
 pub mod connection;
 pub mod devices;
+pub mod discovery;
 pub mod errors;
 pub mod formatter;
+pub mod history;
 pub mod house;
 pub mod receiver;
 pub mod report;
+pub mod retry;
 pub mod room;
@@ -1,79 +1,568 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::net::UdpSocket;
-use std::str;
-use std::sync::{mpsc, Arc, RwLock};
-use std::time::Duration;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 
-use regex::Regex;
+use thermometer::datagram::{self, Datagram, MeasurementKind, Unit};
 
 use crate::connection::ConnectResult;
+use crate::history::{History, Point, Stats};
+
+/// The last value a sensor sent and when it arrived. Values are kept in
+/// their kind's default unit whatever unit the sensor used, e.g. °C for
+/// temperatures, see `to` for others.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub value: f64,
+    pub unit: Unit,
+    pub at: SystemTime,
+}
+
+impl Reading {
+    pub fn age(&self) -> Duration {
+        // a reading from the future, i.e. the clock went back, is as fresh as it gets
+        SystemTime::now()
+            .duration_since(self.at)
+            .unwrap_or_default()
+    }
+
+    /// The same reading in `unit`, `None` if it cannot be given in it.
+    pub fn to(self, unit: Unit) -> Option<Reading> {
+        Some(Reading {
+            value: self.unit.convert(self.value, unit)?,
+            unit,
+            ..self
+        })
+    }
+}
+
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // a converted value is 69.80000000000001°F rather than 69.8°F
+        let value = (self.value * 100.0).round() / 100.0;
+        match self.unit {
+            Unit::Celsius | Unit::Fahrenheit | Unit::Percent => {
+                write!(f, "{}{}", value, self.unit)
+            }
+            _ => write!(f, "{} {}", value, self.unit),
+        }
+    }
+}
 
+#[derive(Debug)]
+struct Sample {
+    value: f64,
+    unit: Unit,
+    received_at: SystemTime,
+    /// Sequence number and send time, unknown for legacy datagrams.
+    order: Option<(u64, SystemTime)>,
+}
+
+impl Sample {
+    /// A telemetry datagram is stale when both its sequence number and its
+    /// send time are behind the last one, i.e. it is a duplicate or arrived
+    /// out of order. A restarted sender counts from zero again, but with a
+    /// later send time, so it gets through.
+    fn is_superseded_by(&self, seq: u64, sent_at: SystemTime) -> bool {
+        match self.order {
+            Some((last_seq, last_sent_at)) => seq > last_seq || sent_at > last_sent_at,
+            None => true,
+        }
+    }
+}
+
+/// Everything a sensor sent of one kind.
+#[derive(Debug)]
+struct Series {
+    last: Sample,
+    history: History,
+}
+
+impl Series {
+    fn reading(&self) -> Reading {
+        Reading {
+            value: self.last.value,
+            unit: self.last.unit,
+            at: self.last.received_at,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Readings {
+    values: RwLock<HashMap<String, HashMap<MeasurementKind, Series>>>,
+    invalid: AtomicU64,
+    discarded: AtomicU64,
+}
+
+impl Readings {
+    fn store(&self, datagram: &[u8], from: SocketAddr) {
+        let datagram = match datagram::parse(datagram) {
+            Ok(datagram) => datagram,
+            Err(e) => {
+                self.invalid.fetch_add(1, Ordering::Relaxed);
+                println!("ignoring datagram from {}: {}", from, e);
+                return;
+            }
+        };
+        let (name, kind, sample) = match datagram {
+            // the legacy format only ever carried temperatures
+            Datagram::Legacy(reading) => (
+                reading.name,
+                MeasurementKind::Temperature,
+                Sample {
+                    value: reading.value,
+                    unit: Unit::Celsius,
+                    received_at: SystemTime::now(),
+                    order: None,
+                },
+            ),
+            // so that a sensor switching units does not mix them in its history
+            Datagram::Telemetry(telemetry) => (
+                telemetry.device,
+                telemetry.kind,
+                Sample {
+                    // `datagram::parse` rejects units the kind does not accept,
+                    // and every unit a kind accepts converts to its default one
+                    value: telemetry
+                        .unit
+                        .convert(telemetry.value, telemetry.kind.unit())
+                        .expect("parse only lets through units the kind accepts"),
+                    unit: telemetry.kind.unit(),
+                    received_at: SystemTime::now(),
+                    order: Some((telemetry.seq, telemetry.sent_at)),
+                },
+            ),
+        };
+
+        let mut values = self.values.write().unwrap();
+        match values.entry(name).or_default().entry(kind) {
+            Entry::Occupied(mut entry) => {
+                let series = entry.get_mut();
+                if let Some((seq, sent_at)) = sample.order {
+                    if !series.last.is_superseded_by(seq, sent_at) {
+                        self.discarded.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                }
+                series.history.record(sample.value, sample.received_at);
+                series.last = sample;
+            }
+            Entry::Vacant(entry) => {
+                let mut history = History::default();
+                history.record(sample.value, sample.received_at);
+                entry.insert(Series {
+                    last: sample,
+                    history,
+                });
+            }
+        }
+    }
+
+    fn with_series<T, F>(&self, name: &str, kind: MeasurementKind, f: F) -> Option<T>
+    where
+        F: FnOnce(&Series) -> T,
+    {
+        self.values
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|sensor| sensor.get(&kind))
+            .map(f)
+    }
+}
+
+/// Collects the readings sensors send over UDP in the background.
+///
+/// The background work stops when the receiver is dropped, or, waiting for
+/// it to finish, on `shutdown`. Without the `no-tokio` feature it is a task
+/// on the current tokio runtime, with it a plain thread.
 #[derive(Debug)]
 pub struct Receiver {
-    data: Arc<RwLock<HashMap<String, f64>>>,
-    done: mpsc::Sender<bool>,
+    data: Arc<Readings>,
+    local_addr: SocketAddr,
+    worker: Option<worker::Worker>,
 }
 
 impl Receiver {
-    #[allow(unreachable_code)]
     pub async fn new(addr: &str) -> ConnectResult<Receiver> {
-        let data = Arc::new(RwLock::new(HashMap::new()));
-        let _data = data.clone();
-        let (done, cancel) = mpsc::channel();
-
-        let socket = UdpSocket::bind(addr).unwrap();
-        #[cfg(feature = "no-tokio")]
-        {
-            use std::thread;
-            thread::spawn(move || {
-                let mut buf: Vec<u8> = vec![0; 64];
-                loop {
-                    let (n, _) = socket.recv_from(&mut buf).unwrap();
-                    let s = str::from_utf8(&buf[..n]).unwrap();
-
-                    if let Some(group) = Regex::new(r"([\w\s_-]+):\t(\d+)").unwrap().captures(s) {
-                        _data.write().unwrap().insert(
-                            group.get(1).unwrap().as_str().to_owned(),
-                            group.get(2).unwrap().as_str().parse().unwrap(),
-                        );
-                    }
-                    if cancel.recv_timeout(Duration::from_millis(1)).is_ok() {
-                        println!("closing receiver...");
-                        break;
-                    }
-                }
-            });
-            return Ok(Self { data, done });
+        let data = Arc::new(Readings::default());
+        let (local_addr, worker) = worker::spawn(addr, data.clone()).await?;
+        Ok(Self {
+            data,
+            local_addr,
+            worker: Some(worker),
+        })
+    }
+
+    /// The last temperature `name` sent.
+    pub fn get_data(&self, name: &str) -> Option<f64> {
+        self.get_reading(name).map(|reading| reading.value)
+    }
+
+    /// The last temperature `name` sent and when.
+    pub fn get_reading(&self, name: &str) -> Option<Reading> {
+        self.get_measurement(name, MeasurementKind::Temperature)
+    }
+
+    pub fn get_measurement(&self, name: &str, kind: MeasurementKind) -> Option<Reading> {
+        self.data.with_series(name, kind, Series::reading)
+    }
+
+    /// The last reading of every kind `name` sent, ordered by kind.
+    pub fn get_measurements(&self, name: &str) -> Vec<(MeasurementKind, Reading)> {
+        let values = self.data.values.read().unwrap();
+        let mut measurements: Vec<_> = values
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|(kind, series)| (*kind, series.reading()))
+            .collect();
+        measurements.sort_by_key(|(kind, _)| *kind);
+        measurements
+    }
+
+    /// Readings that arrived since `since`, averaged per `history::DEFAULT_BUCKET_WIDTH`.
+    pub fn get_history(&self, name: &str, kind: MeasurementKind, since: SystemTime) -> Vec<Point> {
+        self.data
+            .with_series(name, kind, |s| s.history.points(since))
+            .unwrap_or_default()
+    }
+
+    pub fn get_stats(&self, name: &str, kind: MeasurementKind, since: SystemTime) -> Option<Stats> {
+        self.data
+            .with_series(name, kind, |s| s.history.stats(since))
+            .flatten()
+    }
+
+    /// Number of datagrams dropped because they could not be parsed.
+    pub fn invalid_datagrams(&self) -> u64 {
+        self.data.invalid.load(Ordering::Relaxed)
+    }
+
+    /// Number of telemetry datagrams dropped as duplicates or out of order.
+    pub fn discarded_datagrams(&self) -> u64 {
+        self.data.discarded.load(Ordering::Relaxed)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops receiving and waits until the socket is closed.
+    pub async fn shutdown(mut self) {
+        if let Some(worker) = self.worker.take() {
+            worker.stop().await;
         }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        if let Some(worker) = &self.worker {
+            worker.cancel();
+        }
+    }
+}
+
+#[cfg(not(feature = "no-tokio"))]
+mod worker {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
 
-        tokio::spawn(async move {
-            let mut buf: Vec<u8> = vec![0; 64];
+    use thermometer::datagram::MAX_DATAGRAM_LEN;
+    use tokio::net::UdpSocket;
+    use tokio::task::JoinHandle;
+    use tokio_util::sync::CancellationToken;
+
+    use super::Readings;
+    use crate::connection::ConnectResult;
+
+    #[derive(Debug)]
+    pub struct Worker {
+        cancel: CancellationToken,
+        handle: JoinHandle<()>,
+    }
+
+    pub async fn spawn(addr: &str, data: Arc<Readings>) -> ConnectResult<(SocketAddr, Worker)> {
+        let socket = UdpSocket::bind(addr).await?;
+        let local_addr = socket.local_addr()?;
+        let cancel = CancellationToken::new();
+
+        let _cancel = cancel.clone();
+        let handle = tokio::spawn(async move {
+            let mut buf = vec![0; MAX_DATAGRAM_LEN];
             loop {
-                let (n, _) = socket.recv_from(&mut buf).unwrap();
-                let s = str::from_utf8(&buf[..n]).unwrap();
-
-                if let Some(group) = Regex::new(r"([\w\s_-]+):\t(\d+)").unwrap().captures(s) {
-                    _data.write().unwrap().insert(
-                        group.get(1).unwrap().as_str().to_owned(),
-                        group.get(2).unwrap().as_str().parse().unwrap(),
-                    );
+                tokio::select! {
+                    _ = _cancel.cancelled() => break,
+                    res = socket.recv_from(&mut buf) => match res {
+                        Ok((n, from)) => data.store(&buf[..n], from),
+                        Err(e) => {
+                            println!("receiver stopped: {}", e);
+                            break;
+                        }
+                    },
                 }
-                if cancel.recv_timeout(Duration::from_millis(1)).is_ok() {
-                    println!("closing receiver...");
-                    break;
+            }
+        });
+        Ok((local_addr, Worker { cancel, handle }))
+    }
+
+    impl Worker {
+        pub fn cancel(&self) {
+            self.cancel.cancel();
+        }
+
+        pub async fn stop(self) {
+            self.cancel.cancel();
+            let _ = self.handle.await;
+        }
+    }
+}
+
+#[cfg(feature = "no-tokio")]
+mod worker {
+    use std::io;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::channel::oneshot;
+    use thermometer::datagram::MAX_DATAGRAM_LEN;
+
+    use super::Readings;
+    use crate::connection::ConnectResult;
+
+    /// How long the thread may take to notice it has been stopped.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    #[derive(Debug)]
+    pub struct Worker {
+        done: Arc<AtomicBool>,
+        /// Resolves once the thread has exited and closed the socket.
+        exited: oneshot::Receiver<()>,
+    }
+
+    pub async fn spawn(addr: &str, data: Arc<Readings>) -> ConnectResult<(SocketAddr, Worker)> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let local_addr = socket.local_addr()?;
+        let done = Arc::new(AtomicBool::new(false));
+        let (exit, exited) = oneshot::channel();
+
+        let _done = done.clone();
+        thread::spawn(move || {
+            let mut buf = vec![0; MAX_DATAGRAM_LEN];
+            while !_done.load(Ordering::Relaxed) {
+                match socket.recv_from(&mut buf) {
+                    Ok((n, from)) => data.store(&buf[..n], from),
+                    Err(e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut => {}
+                    Err(e) => {
+                        println!("receiver stopped: {}", e);
+                        break;
+                    }
                 }
             }
+            drop(socket);
+            let _ = exit.send(());
         });
-        Ok(Self { data, done })
+        Ok((local_addr, Worker { done, exited }))
     }
 
-    pub fn get_data(&self, name: &str) -> Option<f64> {
-        self.data.read().unwrap().get(name).map(|v| v.to_owned())
+    impl Worker {
+        /// Only tells the thread to stop, it exits within `POLL_INTERVAL`.
+        pub fn cancel(&self) {
+            self.done.store(true, Ordering::Relaxed);
+        }
+
+        /// Waits for the thread without blocking the executor.
+        pub async fn stop(self) {
+            self.cancel();
+            let _ = self.exited.await;
+        }
     }
 }
 
-impl Drop for Receiver {
-    fn drop(&mut self) {
-        self.done.send(true).unwrap();
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use thermometer::datagram::{MeasurementKind, Telemetry, Unit};
+
+    use super::*;
+
+    fn send(to: SocketAddr, datagram: &str) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(datagram.as_bytes(), to).unwrap();
+    }
+
+    fn telemetry(value: f64, seq: u64, sent_at_secs: u64) -> String {
+        measurement(MeasurementKind::Temperature, value, seq, sent_at_secs)
+    }
+
+    fn measurement(kind: MeasurementKind, value: f64, seq: u64, sent_at_secs: u64) -> String {
+        let telemetry = Telemetry {
+            device: "balcony".into(),
+            kind,
+            unit: kind.unit(),
+            value,
+            seq,
+            sent_at: SystemTime::UNIX_EPOCH + Duration::from_secs(sent_at_secs),
+        };
+        String::from_utf8(telemetry.encode()).unwrap()
+    }
+
+    #[test]
+    fn test_receive_readings() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let receiver = Receiver::new("127.0.0.1:0").await.unwrap();
+            send(receiver.local_addr(), "not a reading");
+            send(receiver.local_addr(), "thermometer on the wall:\t23.5");
+            send(receiver.local_addr(), "balcony:\t-3");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(receiver.get_data("thermometer on the wall"), Some(23.5));
+            assert_eq!(receiver.get_data("balcony"), Some(-3.0));
+            assert_eq!(receiver.invalid_datagrams(), 1);
+
+            let stats = receiver
+                .get_stats(
+                    "balcony",
+                    MeasurementKind::Temperature,
+                    SystemTime::UNIX_EPOCH,
+                )
+                .unwrap();
+            assert_eq!((stats.min, stats.max, stats.samples), (-3.0, -3.0, 1));
+            assert_eq!(
+                receiver
+                    .get_history(
+                        "balcony",
+                        MeasurementKind::Temperature,
+                        SystemTime::UNIX_EPOCH
+                    )
+                    .len(),
+                1
+            );
+            assert!(receiver
+                .get_history(
+                    "cellar",
+                    MeasurementKind::Temperature,
+                    SystemTime::UNIX_EPOCH
+                )
+                .is_empty());
+            receiver.shutdown().await;
+        });
+    }
+
+    #[test]
+    fn test_discard_stale_telemetry() {
+        let readings = Readings::default();
+        let from = "127.0.0.1:11601".parse().unwrap();
+        let value = || {
+            readings.values.read().unwrap()["balcony"][&MeasurementKind::Temperature]
+                .last
+                .value
+        };
+
+        readings.store(telemetry(1.0, 5, 100).as_bytes(), from);
+        readings.store(telemetry(2.0, 6, 101).as_bytes(), from);
+        assert_eq!(value(), 2.0);
+        // a duplicate and a late one
+        readings.store(telemetry(2.0, 6, 101).as_bytes(), from);
+        readings.store(telemetry(1.0, 5, 100).as_bytes(), from);
+        assert_eq!(value(), 2.0);
+        assert_eq!(readings.discarded.load(Ordering::Relaxed), 2);
+        // the sender restarted
+        readings.store(telemetry(3.0, 0, 200).as_bytes(), from);
+        assert_eq!(value(), 3.0);
+        // legacy readings carry no order
+        readings.store(b"balcony:\t4", from);
+        assert_eq!(value(), 4.0);
+        readings.store(telemetry(5.0, 0, 0).as_bytes(), from);
+        assert_eq!(value(), 5.0);
+    }
+
+    #[test]
+    fn test_several_kinds_per_sensor() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let receiver = Receiver::new("127.0.0.1:0").await.unwrap();
+            // every kind has a sender and a sequence of its own
+            send(receiver.local_addr(), &telemetry(-3.5, 5, 100));
+            send(
+                receiver.local_addr(),
+                &measurement(MeasurementKind::Humidity, 80.0, 0, 100),
+            );
+            send(
+                receiver.local_addr(),
+                &measurement(MeasurementKind::Battery, 55.0, 0, 100),
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let measurements: Vec<_> = receiver
+                .get_measurements("balcony")
+                .into_iter()
+                .map(|(kind, reading)| (kind, reading.value, reading.unit))
+                .collect();
+            assert_eq!(
+                measurements,
+                vec![
+                    (MeasurementKind::Temperature, -3.5, Unit::Celsius),
+                    (MeasurementKind::Humidity, 80.0, Unit::Percent),
+                    (MeasurementKind::Battery, 55.0, Unit::Percent),
+                ]
+            );
+            assert_eq!(receiver.get_data("balcony"), Some(-3.5));
+            assert_eq!(
+                receiver
+                    .get_measurement("balcony", MeasurementKind::Co2)
+                    .map(|r| r.value),
+                None
+            );
+            assert_eq!(receiver.discarded_datagrams(), 0);
+        });
+    }
+
+    #[test]
+    fn test_normalise_units() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let receiver = Receiver::new("127.0.0.1:0").await.unwrap();
+            send(
+                receiver.local_addr(),
+                r#"{"v":1,"device":"porch","kind":"temperature","unit":"F","value":212,"seq":0,"ts":0}"#,
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let reading = receiver.get_reading("porch").unwrap();
+            assert_eq!((reading.value, reading.unit), (100.0, Unit::Celsius));
+            assert_eq!(reading.to(Unit::Fahrenheit).unwrap().to_string(), "212°F");
+            assert_eq!(reading.to(Unit::Kelvin).unwrap().to_string(), "373.15 K");
+            assert_eq!(reading.to(Unit::Percent), None);
+        });
+    }
+
+    #[test]
+    fn test_shutdown_releases_socket() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            // nothing is ever sent to these receivers, they have to stop anyway
+            let receiver = Receiver::new("127.0.0.1:0").await.unwrap();
+            let addr = receiver.local_addr();
+            receiver.shutdown().await;
+
+            let receiver = Receiver::new(&addr.to_string()).await.unwrap();
+            drop(receiver);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Receiver::new(&addr.to_string()).await.unwrap();
+        });
     }
 }
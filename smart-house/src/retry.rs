@@ -0,0 +1,227 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use smart_socket::protocol::ERR_INTERNAL;
+use tokio::time;
+
+use crate::connection::{Backoff, ConnectError, ConnectResult};
+
+/// Tells a failure that may go away on its own from one that will not.
+///
+/// Lost connections, timeouts and server side errors are worth another try,
+/// a rejected request, a response we cannot understand or a device the
+/// circuit breaker keeps away are not.
+pub fn is_transient(error: &ConnectError) -> bool {
+    match error {
+        ConnectError::Io(_) | ConnectError::Timeout(_) | ConnectError::Frame(_) => true,
+        ConnectError::Remote { code, .. } => *code >= ERR_INTERNAL,
+        ConnectError::Parse(_)
+        | ConnectError::UnexpectedResponse(_)
+        | ConnectError::CircuitOpen(_) => false,
+    }
+}
+
+/// How to repeat an operation that failed.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Delays between attempts and how many attempts to make.
+    pub backoff: Backoff,
+    /// Part of every delay, from 0 to 1, that is picked at random so that
+    /// clients failing together do not retry together.
+    pub jitter: f64,
+    /// Gives up once this much time has passed since the first attempt.
+    pub max_elapsed: Option<Duration>,
+    /// Returns `true` for errors worth another attempt.
+    pub is_retryable: fn(&ConnectError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::default(),
+            jitter: 0.5,
+            max_elapsed: Some(Duration::from_secs(10)),
+            is_retryable: is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Tries exactly once.
+    pub fn never() -> Self {
+        Self {
+            backoff: Backoff {
+                attempts: 1,
+                ..Backoff::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    pub fn start(&self) -> RetryState<'_> {
+        RetryState {
+            policy: self,
+            attempt: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Runs `op` until it succeeds, fails with an error that is not retryable
+    /// or the policy gives up, and returns the last result.
+    pub async fn run<T, F, Fut>(&self, mut op: F) -> ConnectResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ConnectResult<T>>,
+    {
+        let mut state = self.start();
+        loop {
+            match op().await {
+                Err(e) => match state.next_delay(&e) {
+                    Some(delay) => time::sleep(delay).await,
+                    None => return Err(e),
+                },
+                ok => return ok,
+            }
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.backoff.delay(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        delay.mul_f64(1.0 - jitter * rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Attempts made so far under a `RetryPolicy`, for loops `RetryPolicy::run`
+/// cannot express, e.g. the ones borrowing the device mutably.
+#[derive(Debug)]
+pub struct RetryState<'a> {
+    policy: &'a RetryPolicy,
+    attempt: u32,
+    started: Instant,
+}
+
+impl RetryState<'_> {
+    /// Records a failed attempt. Returns how long to wait before the next one,
+    /// or `None` if there should be no next one.
+    pub fn next_delay(&mut self, error: &ConnectError) -> Option<Duration> {
+        self.attempt += 1;
+        if self.attempt >= self.policy.backoff.attempts || !(self.policy.is_retryable)(error) {
+            return None;
+        }
+        let delay = self.policy.delay(self.attempt - 1);
+        match self.policy.max_elapsed {
+            Some(max) if self.started.elapsed() + delay > max => None,
+            _ => Some(delay),
+        }
+    }
+
+    /// Number of failed attempts so far.
+    pub fn failures(&self) -> u32 {
+        self.attempt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn policy(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            backoff: Backoff {
+                initial: Duration::from_millis(1),
+                max: Duration::from_millis(5),
+                attempts,
+            },
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn test_classify_errors() {
+        assert!(is_transient(&ConnectError::Io(
+            io::ErrorKind::ConnectionReset.into()
+        )));
+        assert!(is_transient(&ConnectError::Remote {
+            code: 500,
+            message: "relay is stuck".into()
+        }));
+        assert!(!is_transient(&ConnectError::Remote {
+            code: 401,
+            message: "authentication required".into()
+        }));
+        assert!(!is_transient(&ConnectError::UnexpectedResponse(
+            "OK".into()
+        )));
+    }
+
+    #[test]
+    fn test_delays_with_jitter() {
+        let policy = RetryPolicy {
+            backoff: Backoff {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(1),
+                attempts: 10,
+            },
+            // leaves a gap between the possible delays, 240-400ms after the
+            // third failure and 480-800ms after the fourth
+            jitter: 0.4,
+            max_elapsed: Some(Duration::from_millis(450)),
+            is_retryable: is_transient,
+        };
+        let error = ConnectError::Timeout(Duration::from_secs(1));
+        let mut state = policy.start();
+        let first = state.next_delay(&error).unwrap();
+        assert!(first >= Duration::from_millis(60) && first <= Duration::from_millis(100));
+        let second = state.next_delay(&error).unwrap();
+        assert!(second >= Duration::from_millis(120) && second <= Duration::from_millis(200));
+
+        // the delay after the fourth failure would not fit into max_elapsed
+        state.next_delay(&error).unwrap();
+        assert_eq!(state.next_delay(&error), None);
+    }
+
+    #[test]
+    fn test_run_retries_transient_errors_only() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let calls = AtomicU32::new(0);
+            let res = policy(5)
+                .run(|| async {
+                    match calls.fetch_add(1, Ordering::SeqCst) {
+                        0 | 1 => Err(ConnectError::Timeout(Duration::from_secs(1))),
+                        n => Ok(n),
+                    }
+                })
+                .await;
+            assert_eq!(res.unwrap(), 2);
+
+            calls.store(0, Ordering::SeqCst);
+            let res: ConnectResult<()> = policy(5)
+                .run(|| async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Err(ConnectError::UnexpectedResponse("OK".into()))
+                })
+                .await;
+            assert!(res.is_err());
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+            calls.store(0, Ordering::SeqCst);
+            let res: ConnectResult<()> = policy(3)
+                .run(|| async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Err(ConnectError::Timeout(Duration::from_secs(1)))
+                })
+                .await;
+            assert!(res.is_err());
+            assert_eq!(calls.load(Ordering::SeqCst), 3);
+        });
+    }
+}
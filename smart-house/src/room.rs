@@ -3,6 +3,7 @@ use std::sync::{Arc, Mutex};
 
 use crate::connection::ConnectResult;
 use crate::devices::device::Device;
+use crate::devices::sensor::Sensor;
 use crate::devices::smartsocket::SmartSocket;
 use crate::devices::thermometer::Thermometer;
 use crate::devices::types::DeviceType;
@@ -25,8 +26,16 @@ impl Room {
         }
     }
 
+    /// Starts receiving thermometer readings on `addr`, shutting down the
+    /// receiver mounted before, if any. Connected thermometers keep working
+    /// with the new one.
     pub async fn mount_receiver(&mut self, addr: &str) -> ConnectResult<()> {
-        self.receiver = Arc::new(Mutex::new(Some(Receiver::new(addr).await?)));
+        let old = self.receiver.lock().unwrap().take();
+        if let Some(old) = old {
+            old.shutdown().await;
+        }
+        let receiver = Receiver::new(addr).await?;
+        *self.receiver.lock().unwrap() = Some(receiver);
         Ok(())
     }
 
@@ -35,7 +44,7 @@ impl Room {
     }
 
     pub fn add_device(&mut self, device: DeviceType) -> Result<(), HouseUpdateErr> {
-        if self.devices.get(device.get_name()).is_none() {
+        if !self.devices.contains_key(device.get_name()) {
             self.devices.insert(device.get_name().to_owned(), device);
             return Ok(());
         }
@@ -45,7 +54,7 @@ impl Room {
     }
 
     pub fn remove_device(&mut self, name: &str) -> Result<(), HouseUpdateErr> {
-        if self.devices.get(name).is_some() {
+        if self.devices.contains_key(name) {
             self.devices.remove(name);
             return Ok(());
         }
@@ -88,10 +97,27 @@ impl Room {
         None
     }
 
+    pub fn get_sensor(&self, name: &str) -> Option<&Sensor> {
+        if let Some(DeviceType::Sensor(ref s)) = self.devices.get(name) {
+            return Some(s);
+        }
+        None
+    }
+
+    pub fn get_sensor_mut(&mut self, name: &str) -> Option<&mut Sensor> {
+        if let Some(DeviceType::Sensor(ref mut s)) = self.devices.get_mut(name) {
+            return Some(s);
+        }
+        None
+    }
+
+    /// Makes a thermometer or a sensor read from the room's receiver.
     pub fn connect_device_to_receiver(&mut self, name: &str) {
         let receiver = self.receiver.clone();
-        if let Some(t) = self.get_thermometer_mut(name) {
-            t.add_receiver(receiver);
+        match self.devices.get_mut(name) {
+            Some(DeviceType::Thermometer(t)) => t.add_receiver(receiver),
+            Some(DeviceType::Sensor(s)) => s.add_receiver(receiver),
+            _ => {}
         }
     }
 }
@@ -1,60 +1,57 @@
-use std::io::{Read, Write};
-use std::str::{self, FromStr};
-use std::string::ToString;
-
-use smart_socket::protocol::*;
+use discovery::DEFAULT_DISCOVERY_ADDRESS;
+use smart_socket::load::LoadProfile;
 use smart_socket::receiver::*;
+use smart_socket::tls;
 
-enum Continuation {
-    Continue,
-    Stop,
-}
-
-fn handle_error(addr: &str, res: Result<(), std::io::Error>) -> Continuation {
-    if let Err(e) = res {
-        println!("got error from client {}: {}", addr, e);
-        return Continuation::Stop;
-    }
-    Continuation::Continue
-}
+const USAGE: &str = "usage: smart_socket_tcp [address] [--profile <profile>] [--state <file>] \
+                     [--name <name>] [--secret <secret>] [--cert <pem> --key <pem>]";
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut state = SmartSocketState::default();
-    let receiver = SmartSocketReceiver::bind(
-        std::env::args()
-            .nth(1)
-            .unwrap_or_else(|| DEFAULT_ADDRESS.to_string()),
-    )?;
-
-    for connection in receiver.incoming() {
-        match connection {
-            Ok(mut stream) => loop {
-                let addr = stream.peer_addr().unwrap().to_string();
-                // protocol expects commands with \r\n at the end:
-                //  status\r\n
-                //  switch\r\n
-                let mut buf = vec![0; 8];
-                if let Continuation::Stop = handle_error(&addr, stream.read_exact(&mut buf)) {
-                    break;
-                }
+    let mut addr = DEFAULT_ADDRESS.to_string();
+    // load profile, e.g. constant:60, random:10:100:5 or replay:load.csv
+    let mut profile: Option<LoadProfile> = None;
+    // file to keep the socket state in between restarts
+    let mut state: Option<String> = None;
+    // name to answer discovery probes with
+    let mut name: Option<String> = None;
+    // shared secret clients have to authenticate with
+    let mut secret: Option<String> = None;
+    // certificate chain and private key to serve TLS with
+    let mut cert: Option<String> = None;
+    let mut key: Option<String> = None;
 
-                let s = str::from_utf8(&buf)?;
-                match ProtocolCommand::from_str(s) {
-                    Ok(ProtocolCommand::Switch) => {
-                        state.switch();
-                        handle_error(&addr, stream.write_all(OK.as_bytes()));
-                    }
-                    Ok(ProtocolCommand::Status) => {
-                        handle_error(&addr, write!(stream, "{}", state.status()));
-                    }
-                    Err(e) => {
-                        handle_error(&addr, write!(stream, "{}", e));
-                        break;
-                    }
-                };
-            },
-            Err(e) => return Err(Box::new(BindError::Io(e))),
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--profile" => profile = Some(args.next().ok_or(USAGE)?.parse()?),
+            "--state" => state = Some(args.next().ok_or(USAGE)?),
+            "--name" => name = Some(args.next().ok_or(USAGE)?),
+            "--secret" => secret = Some(args.next().ok_or(USAGE)?),
+            "--cert" => cert = Some(args.next().ok_or(USAGE)?),
+            "--key" => key = Some(args.next().ok_or(USAGE)?),
+            _ if arg.starts_with("--") => return Err(USAGE.into()),
+            _ => addr = arg,
         }
     }
+
+    let mut server = match state {
+        Some(path) => SmartSocketServer::bind_persistent(addr, path)?,
+        None => SmartSocketServer::bind(addr)?,
+    };
+    if let Some(profile) = profile {
+        server.state().lock().unwrap().set_profile(profile);
+    }
+    match (cert, key) {
+        (Some(cert), Some(key)) => server.set_tls(tls::server_config(cert, key)?),
+        (None, None) => (),
+        _ => return Err(USAGE.into()),
+    }
+    if let Some(secret) = secret {
+        server.set_secret(&secret);
+    }
+    if let Some(name) = name {
+        server.enable_discovery(DEFAULT_DISCOVERY_ADDRESS, &name)?;
+    }
+    server.serve()?;
     Ok(())
 }
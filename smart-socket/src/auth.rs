@@ -0,0 +1,68 @@
+use std::fmt::Write;
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_LEN: usize = 16;
+
+/// Random hex challenge the client has to sign with the shared secret.
+pub fn new_nonce() -> String {
+    let mut nonce = [0; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    to_hex(&nonce)
+}
+
+/// Hex encoded HMAC-SHA256 of `nonce` keyed with `secret`.
+pub fn sign(secret: &[u8], nonce: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(nonce.as_bytes());
+    to_hex(&mac.finalize().into_bytes())
+}
+
+/// Checks `signature` in constant time.
+pub fn verify(secret: &[u8], nonce: &str, signature: &str) -> bool {
+    let signature = match from_hex(signature) {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(nonce.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let nonce = new_nonce();
+        assert_eq!(nonce.len(), NONCE_LEN * 2);
+
+        let signature = sign(b"secret", &nonce);
+        assert!(verify(b"secret", &nonce, &signature));
+        assert!(!verify(b"another secret", &nonce, &signature));
+        assert!(!verify(b"secret", &new_nonce(), &signature));
+        assert!(!verify(b"secret", &nonce, "not hex"));
+    }
+}
@@ -1,2 +1,6 @@
+pub mod auth;
+pub mod load;
 pub mod protocol;
 pub mod receiver;
+pub mod store;
+pub mod tls;
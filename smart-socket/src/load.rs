@@ -0,0 +1,178 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use sampling::{parse_csv, InvalidCsvRow};
+use thiserror::Error;
+
+pub const DEFAULT_WATTS: f64 = 2.0;
+
+/// Describes how much power the plugged-in appliance draws while the socket is on.
+///
+/// Profiles are written as `constant:<W>`, `random:<min W>:<max W>:<step W>`
+/// or `replay:<path to csv>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadProfile {
+    Constant(f64),
+    /// Moves by at most `step` watts on every sample, staying in `min..=max`.
+    RandomWalk {
+        min: f64,
+        max: f64,
+        step: f64,
+    },
+    /// Repeats `seconds,watts` rows of a CSV file in a loop. The last row
+    /// marks where the loop ends: at its time the replay starts over from
+    /// the first row, so its watts are never drawn unless it is the only row.
+    Replay {
+        path: PathBuf,
+        samples: Vec<(Duration, f64)>,
+    },
+}
+
+impl Default for LoadProfile {
+    fn default() -> Self {
+        LoadProfile::Constant(DEFAULT_WATTS)
+    }
+}
+
+impl LoadProfile {
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self, LoadProfileError> {
+        let path = path.as_ref();
+        let samples = parse_csv(&fs::read_to_string(path)?)?;
+        Ok(LoadProfile::Replay {
+            path: path.to_owned(),
+            samples,
+        })
+    }
+
+    /// Returns the power drawn `elapsed` after the socket was turned on,
+    /// given the previous sample `last`.
+    pub fn sample(&self, elapsed: Duration, last: f64) -> f64 {
+        match self {
+            LoadProfile::Constant(watts) => *watts,
+            LoadProfile::RandomWalk { min, max, step } => {
+                sampling::random_walk(last, *min, *max, *step)
+            }
+            LoadProfile::Replay { samples, .. } => sampling::replay(samples, elapsed),
+        }
+    }
+
+    /// The value a fresh socket starts from.
+    pub fn initial(&self) -> f64 {
+        match self {
+            LoadProfile::Constant(watts) => *watts,
+            LoadProfile::RandomWalk { min, max, .. } => (min + max) / 2.0,
+            LoadProfile::Replay { .. } => self.sample(Duration::ZERO, 0.0),
+        }
+    }
+}
+
+impl fmt::Display for LoadProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadProfile::Constant(watts) => write!(f, "constant:{}", watts),
+            LoadProfile::RandomWalk { min, max, step } => {
+                write!(f, "random:{}:{}:{}", min, max, step)
+            }
+            LoadProfile::Replay { path, .. } => write!(f, "replay:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for LoadProfile {
+    type Err = LoadProfileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || LoadProfileError::InvalidProfile(s.to_owned());
+        let number = |v: &str| {
+            v.parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(invalid)
+        };
+
+        let (kind, args) = s.trim().split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "constant" => Ok(LoadProfile::Constant(number(args)?)),
+            "random" => match args.split(':').collect::<Vec<_>>()[..] {
+                [min, max, step] => {
+                    let (min, max, step) = (number(min)?, number(max)?, number(step)?);
+                    if min > max || step < 0.0 {
+                        return Err(invalid());
+                    }
+                    Ok(LoadProfile::RandomWalk { min, max, step })
+                }
+                _ => Err(invalid()),
+            },
+            "replay" => LoadProfile::replay(args),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LoadProfileError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid load profile: {0}")]
+    InvalidProfile(String),
+    #[error("invalid csv row {0}")]
+    InvalidCsvRow(usize),
+}
+
+impl From<InvalidCsvRow> for LoadProfileError {
+    fn from(e: InvalidCsvRow) -> Self {
+        LoadProfileError::InvalidCsvRow(e.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_walk_stays_in_bounds() {
+        let profile: LoadProfile = "random:10:20:5".parse().unwrap();
+        let mut watts = profile.initial();
+        for i in 0..1000 {
+            watts = profile.sample(Duration::from_secs(i), watts);
+            assert!((10.0..=20.0).contains(&watts));
+        }
+    }
+
+    #[test]
+    fn test_replay_loops_over_samples() {
+        let profile = LoadProfile::Replay {
+            path: PathBuf::from("load.csv"),
+            samples: parse_csv("seconds,watts\n0,100\n10,5\n20,100\n").unwrap(),
+        };
+        assert_eq!(profile.sample(Duration::from_secs(3), 0.0), 100.0);
+        assert_eq!(profile.sample(Duration::from_secs(15), 0.0), 5.0);
+        assert_eq!(profile.sample(Duration::from_secs(25), 0.0), 100.0);
+        assert_eq!(profile.sample(Duration::from_secs(32), 0.0), 5.0);
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        assert_eq!(
+            "constant:60".parse::<LoadProfile>().unwrap(),
+            LoadProfile::Constant(60.0)
+        );
+        assert_eq!(
+            LoadProfile::RandomWalk {
+                min: 1.0,
+                max: 2.5,
+                step: 0.5
+            }
+            .to_string(),
+            "random:1:2.5:0.5"
+        );
+        assert!("random:5:1:1".parse::<LoadProfile>().is_err());
+        assert!("solar:1".parse::<LoadProfile>().is_err());
+        assert!("random:NaN:5:1".parse::<LoadProfile>().is_err());
+        assert!("constant:inf".parse::<LoadProfile>().is_err());
+    }
+}
@@ -1,20 +1,50 @@
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::str::{self, FromStr};
 
 use thiserror::Error;
 
-pub const OK: &str = "OK\r\n";
+pub const OK: &str = "OK";
+pub const ERR: &str = "ERR";
+
+/// The request could not be parsed or is not supported.
+pub const ERR_BAD_REQUEST: u16 = 400;
+/// The server requires the client to authenticate first.
+pub const ERR_UNAUTHENTICATED: u16 = 401;
+/// The client's answer to the challenge is wrong.
+pub const ERR_AUTH_FAILED: u16 = 403;
+/// The server failed to fulfil a valid request.
+pub const ERR_INTERNAL: u16 = 500;
+
+/// Every request and response on the wire is terminated by this delimiter.
+pub const DELIMITER: &[u8] = b"\r\n";
+
+/// Upper bound for a single frame, the delimiter excluded.
+pub const MAX_FRAME_LEN: usize = 1024;
 
 #[derive(Debug)]
 pub enum ProtocolCommand {
     Switch,
+    On,
+    Off,
     Status,
+    Energy,
+    /// Asks for a nonce to sign with the shared secret.
+    Challenge,
+    /// Carries the hex encoded signature of the last challenge.
+    Auth(String),
 }
 
-impl ToString for ProtocolCommand {
-    fn to_string(&self) -> String {
+impl fmt::Display for ProtocolCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ProtocolCommand::Switch => "switch\r\n".to_owned(),
-            ProtocolCommand::Status => "status\r\n".to_owned(),
+            ProtocolCommand::Switch => write!(f, "switch"),
+            ProtocolCommand::On => write!(f, "on"),
+            ProtocolCommand::Off => write!(f, "off"),
+            ProtocolCommand::Status => write!(f, "status"),
+            ProtocolCommand::Energy => write!(f, "energy"),
+            ProtocolCommand::Challenge => write!(f, "challenge"),
+            ProtocolCommand::Auth(signature) => write!(f, "auth {}", signature),
         }
     }
 }
@@ -25,14 +55,273 @@ impl FromStr for ProtocolCommand {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.trim() {
             "switch" => Ok(ProtocolCommand::Switch),
+            "on" => Ok(ProtocolCommand::On),
+            "off" => Ok(ProtocolCommand::Off),
             "status" => Ok(ProtocolCommand::Status),
-            other => Err(ParseError::UnknownCommand(other.to_owned())),
+            "energy" => Ok(ProtocolCommand::Energy),
+            "challenge" => Ok(ProtocolCommand::Challenge),
+            other => match other.split_once(' ') {
+                Some(("auth", signature)) => Ok(ProtocolCommand::Auth(signature.trim().to_owned())),
+                _ => Err(ParseError::UnknownCommand(other.to_owned())),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolResponse {
+    Ok,
+    Status {
+        on: bool,
+        watts: f64,
+    },
+    /// Energy consumed since the socket state was created, in watt-hours.
+    Energy {
+        wh: f64,
+    },
+    /// A nonce the client signs and sends back with `auth`.
+    Challenge {
+        nonce: String,
+    },
+    Error {
+        code: u16,
+        message: String,
+    },
+}
+
+impl fmt::Display for ProtocolResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolResponse::Ok => write!(f, "{}", OK),
+            ProtocolResponse::Status { on: true, watts } => write!(f, "is on ({}W)", watts),
+            ProtocolResponse::Status { on: false, .. } => write!(f, "is off"),
+            ProtocolResponse::Energy { wh } => write!(f, "consumed {}Wh", wh),
+            ProtocolResponse::Challenge { nonce } => write!(f, "challenge {}", nonce),
+            ProtocolResponse::Error { code, message } => write!(f, "{} {} {}", ERR, code, message),
         }
     }
 }
 
+impl FromStr for ProtocolResponse {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == OK {
+            return Ok(ProtocolResponse::Ok);
+        }
+        if s == "is off" {
+            return Ok(ProtocolResponse::Status {
+                on: false,
+                watts: 0.0,
+            });
+        }
+        if let Some(rest) = s.strip_prefix(ERR) {
+            let (code, message) = rest
+                .trim_start()
+                .split_once(' ')
+                .unwrap_or((rest.trim(), ""));
+            return code
+                .parse()
+                .map(|code| ProtocolResponse::Error {
+                    code,
+                    message: message.to_owned(),
+                })
+                .map_err(|_| ParseError::UnknownResponse(s.to_owned()));
+        }
+        if let Some(nonce) = s.strip_prefix("challenge ") {
+            return Ok(ProtocolResponse::Challenge {
+                nonce: nonce.to_owned(),
+            });
+        }
+        if let Some(wh) = s
+            .strip_prefix("consumed ")
+            .and_then(|rest| rest.strip_suffix("Wh"))
+        {
+            return wh
+                .parse()
+                .map(|wh| ProtocolResponse::Energy { wh })
+                .map_err(|_| ParseError::UnknownResponse(s.to_owned()));
+        }
+        s.strip_prefix("is on (")
+            .and_then(|rest| rest.strip_suffix("W)"))
+            .and_then(|watts| watts.parse().ok())
+            .map(|watts| ProtocolResponse::Status { on: true, watts })
+            .ok_or_else(|| ParseError::UnknownResponse(s.to_owned()))
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ParseError {
-    #[error("Unknown command: {0}\r\n")]
+    #[error("Unknown command: {0}")]
     UnknownCommand(String),
+    #[error("Unknown response: {0}")]
+    UnknownResponse(String),
+}
+
+/// Serializes `item` into a single frame, delimiter included.
+pub fn encode<T: fmt::Display>(item: &T) -> Vec<u8> {
+    let mut frame = item.to_string().into_bytes();
+    frame.extend_from_slice(DELIMITER);
+    frame
+}
+
+/// Accumulates raw bytes and splits them into frames. It does no I/O by itself,
+/// so it serves blocking and async streams alike.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Pops the next complete frame without its delimiter, if one is buffered.
+    pub fn decode(&mut self) -> Result<Option<String>, FrameError> {
+        match self
+            .buf
+            .windows(DELIMITER.len())
+            .position(|w| w == DELIMITER)
+        {
+            Some(pos) if pos > MAX_FRAME_LEN => Err(FrameError::TooLong(MAX_FRAME_LEN)),
+            Some(pos) => {
+                let frame: Vec<u8> = self.buf.drain(..pos + DELIMITER.len()).take(pos).collect();
+                String::from_utf8(frame)
+                    .map(Some)
+                    .map_err(|_| FrameError::InvalidUtf8)
+            }
+            None if self.buf.len() > MAX_FRAME_LEN + DELIMITER.len() => {
+                Err(FrameError::TooLong(MAX_FRAME_LEN))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Blocking stream that reads and writes whole frames.
+pub struct FramedStream<S> {
+    stream: S,
+    decoder: FrameDecoder,
+}
+
+impl<S: Read + Write> FramedStream<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            decoder: FrameDecoder::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns `None` once the peer has closed the stream.
+    pub fn read_frame(&mut self) -> Result<Option<String>, FrameError> {
+        let mut buf = [0; 256];
+        loop {
+            if let Some(frame) = self.decoder.decode()? {
+                return Ok(Some(frame));
+            }
+            match self.stream.read(&mut buf)? {
+                0 => return Ok(None),
+                n => self.decoder.extend(&buf[..n]),
+            }
+        }
+    }
+
+    pub fn write_frame<T: fmt::Display>(&mut self, item: &T) -> io::Result<()> {
+        self.stream.write_all(&encode(item))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FrameError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("frame is longer than {0} bytes")]
+    TooLong(usize),
+    #[error("frame is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_partial_frames() {
+        let mut decoder = FrameDecoder::default();
+        decoder.extend(b"sta");
+        assert_eq!(decoder.decode().unwrap(), None);
+        decoder.extend(b"tus\r");
+        assert_eq!(decoder.decode().unwrap(), None);
+        decoder.extend(b"\nswitch\r\nst");
+        assert_eq!(decoder.decode().unwrap(), Some("status".into()));
+        assert_eq!(decoder.decode().unwrap(), Some("switch".into()));
+        assert_eq!(decoder.decode().unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_too_long_frame() {
+        let mut decoder = FrameDecoder::default();
+        decoder.extend(&vec![b'a'; MAX_FRAME_LEN + 3]);
+        assert!(matches!(decoder.decode(), Err(FrameError::TooLong(_))));
+    }
+
+    #[test]
+    fn test_response_roundtrip() {
+        for response in [
+            ProtocolResponse::Ok,
+            ProtocolResponse::Status {
+                on: true,
+                watts: 2.5,
+            },
+            ProtocolResponse::Status {
+                on: false,
+                watts: 0.0,
+            },
+            ProtocolResponse::Energy { wh: 0.125 },
+            ProtocolResponse::Challenge {
+                nonce: "0badc0ffee".into(),
+            },
+            ProtocolResponse::Error {
+                code: ERR_BAD_REQUEST,
+                message: "Unknown command: foo".into(),
+            },
+        ] {
+            let frame = String::from_utf8(encode(&response)).unwrap();
+            assert_eq!(frame.parse::<ProtocolResponse>().unwrap(), response);
+        }
+        assert_eq!(
+            encode(&ProtocolResponse::Status {
+                on: true,
+                watts: 2.0
+            }),
+            b"is on (2W)\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_auth_command() {
+        match "auth 0badc0ffee".parse::<ProtocolCommand>().unwrap() {
+            ProtocolCommand::Auth(signature) => assert_eq!(signature, "0badc0ffee"),
+            other => panic!("expected auth, got {:?}", other),
+        }
+        assert!("auth".parse::<ProtocolCommand>().is_err());
+    }
+
+    #[test]
+    fn test_parse_error_response() {
+        assert_eq!(
+            "ERR 500 out of order".parse::<ProtocolResponse>().unwrap(),
+            ProtocolResponse::Error {
+                code: ERR_INTERNAL,
+                message: "out of order".into(),
+            }
+        );
+        assert!("ERR five".parse::<ProtocolResponse>().is_err());
+    }
 }
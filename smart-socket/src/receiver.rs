@@ -1,25 +1,165 @@
-use std::io;
-use std::net::{Incoming, TcpListener, ToSocketAddrs};
+use std::io::{self, Read, Write};
+use std::net::{Incoming, SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use discovery::{Announcement, DeviceKind, Responder};
 use thiserror::Error;
 
+use crate::auth;
+use crate::load::LoadProfile;
+use crate::protocol::{
+    FrameError, FramedStream, ProtocolCommand, ProtocolResponse, ERR_AUTH_FAILED, ERR_BAD_REQUEST,
+    ERR_UNAUTHENTICATED,
+};
+use crate::store::{StoreError, StoredState};
+use crate::tls::{self, rustls::ServerConfig};
+
 pub const DEFAULT_ADDRESS: &str = "127.0.0.1:10701";
 
-#[derive(Default)]
+/// A client that sends nothing for this long is hung up on, so that it
+/// does not hold its thread forever.
+pub const DEFAULT_CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The energy counter grows on every status poll, but is written to the
+/// state file at most this often. Switching is saved right away.
+pub const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct SmartSocketState {
     is_on: bool,
+    profile: LoadProfile,
+    watts: f64,
+    energy_wh: f64,
+    turned_on_at: Instant,
+    updated_at: Instant,
+    store: Option<PathBuf>,
+    persisted_at: Instant,
+}
+
+impl Default for SmartSocketState {
+    fn default() -> Self {
+        Self::new(LoadProfile::default())
+    }
 }
 
 impl SmartSocketState {
-    pub fn status(&self) -> String {
-        match self.is_on {
-            true => "is on (2W)\r\n".to_owned(),
-            false => "is off\r\n".to_owned(),
+    pub fn new(profile: LoadProfile) -> Self {
+        let now = Instant::now();
+        Self {
+            is_on: false,
+            watts: profile.initial(),
+            profile,
+            energy_wh: 0.0,
+            turned_on_at: now,
+            updated_at: now,
+            store: None,
+            persisted_at: now,
         }
     }
 
+    /// Loads the state stored at `path`, if any, and keeps saving every
+    /// change back there, see `PERSIST_INTERVAL`.
+    pub fn restore<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let path = path.as_ref();
+        let mut state = match StoredState::load(path)? {
+            Some(stored) => {
+                let mut state = Self::new(stored.profile);
+                state.is_on = stored.is_on;
+                state.energy_wh = stored.energy_wh;
+                state
+            }
+            None => Self::default(),
+        };
+        state.store = Some(path.to_owned());
+        Ok(state)
+    }
+
+    pub fn set_profile(&mut self, profile: LoadProfile) {
+        self.update(Instant::now());
+        self.watts = profile.initial();
+        self.profile = profile;
+        self.persist();
+    }
+
+    pub fn status(&mut self) -> ProtocolResponse {
+        self.update(Instant::now());
+        self.persist_if_due();
+        ProtocolResponse::Status {
+            on: self.is_on,
+            watts: if self.is_on { self.watts } else { 0.0 },
+        }
+    }
+
+    pub fn energy(&mut self) -> ProtocolResponse {
+        self.update(Instant::now());
+        self.persist_if_due();
+        ProtocolResponse::Energy { wh: self.energy_wh }
+    }
+
     pub fn switch(&mut self) {
-        self.is_on = !self.is_on;
+        self.set_at(!self.is_on, Instant::now());
+        self.persist();
+    }
+
+    /// Unlike `switch`, repeating the call leaves the socket in the same state.
+    pub fn set(&mut self, on: bool) {
+        self.set_at(on, Instant::now());
+        self.persist();
+    }
+
+    fn set_at(&mut self, on: bool, now: Instant) {
+        self.update(now);
+        if on && !self.is_on {
+            self.turned_on_at = now;
+            self.watts = self.profile.sample(Duration::ZERO, self.watts);
+        }
+        self.is_on = on;
+    }
+
+    /// Accounts the energy drawn since the last update at the previous power
+    /// level, then samples the load profile for the next period.
+    fn update(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        if self.is_on {
+            self.energy_wh += self.watts * elapsed.as_secs_f64() / 3600.0;
+            self.watts = self
+                .profile
+                .sample(now.saturating_duration_since(self.turned_on_at), self.watts);
+        }
+        self.updated_at = now;
+    }
+
+    fn persist_if_due(&mut self) {
+        if self.updated_at.saturating_duration_since(self.persisted_at) >= PERSIST_INTERVAL {
+            self.persist();
+        }
+    }
+
+    fn persist(&mut self) {
+        self.persisted_at = self.updated_at;
+        if let Some(path) = &self.store {
+            let stored = StoredState {
+                is_on: self.is_on,
+                energy_wh: self.energy_wh,
+                profile: self.profile.clone(),
+            };
+            if let Err(e) = stored.save(path) {
+                println!("cannot save state to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Saves the energy drawn since the last save.
+impl Drop for SmartSocketState {
+    fn drop(&mut self) {
+        if self.store.is_some() {
+            self.update(Instant::now());
+            self.persist();
+        }
     }
 }
 
@@ -27,6 +167,7 @@ pub type BindResult = Result<SmartSocketReceiver, BindError>;
 
 pub struct SmartSocketReceiver {
     tcp: TcpListener,
+    tls: Option<Arc<ServerConfig>>,
 }
 
 impl SmartSocketReceiver {
@@ -35,16 +176,481 @@ impl SmartSocketReceiver {
         Addrs: ToSocketAddrs,
     {
         let tcp = TcpListener::bind(addr)?;
-        Ok(Self { tcp })
+        Ok(Self { tcp, tls: None })
+    }
+
+    /// Like `bind`, but clients have to talk TLS.
+    pub fn bind_tls<Addrs>(addr: Addrs, config: Arc<ServerConfig>) -> BindResult
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let tcp = TcpListener::bind(addr)?;
+        Ok(Self {
+            tcp,
+            tls: Some(config),
+        })
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
     }
 
     pub fn incoming(&self) -> Incoming<'_> {
         self.tcp.incoming()
     }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+}
+
+/// Serves every accepted client on its own thread, all of them sharing
+/// a single `SmartSocketState`.
+pub struct SmartSocketServer {
+    receiver: SmartSocketReceiver,
+    state: Arc<Mutex<SmartSocketState>>,
+    responder: Option<Responder>,
+    secret: Option<Arc<[u8]>>,
+    client_timeout: Duration,
+}
+
+impl SmartSocketServer {
+    pub fn new(receiver: SmartSocketReceiver) -> Self {
+        Self {
+            receiver,
+            state: Arc::new(Mutex::new(SmartSocketState::default())),
+            responder: None,
+            secret: None,
+            client_timeout: DEFAULT_CLIENT_TIMEOUT,
+        }
+    }
+
+    pub fn bind<Addrs>(addr: Addrs) -> Result<Self, BindError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        Ok(Self::new(SmartSocketReceiver::bind(addr)?))
+    }
+
+    /// Like `bind`, but restores the socket state from `path` and saves it
+    /// there on every change.
+    pub fn bind_persistent<Addrs, P>(addr: Addrs, path: P) -> Result<Self, BindError>
+    where
+        Addrs: ToSocketAddrs,
+        P: AsRef<Path>,
+    {
+        let state = SmartSocketState::restore(path)?;
+        Ok(Self {
+            receiver: SmartSocketReceiver::bind(addr)?,
+            state: Arc::new(Mutex::new(state)),
+            responder: None,
+            secret: None,
+            client_timeout: DEFAULT_CLIENT_TIMEOUT,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.receiver.local_addr()
+    }
+
+    pub fn state(&self) -> Arc<Mutex<SmartSocketState>> {
+        self.state.clone()
+    }
+
+    /// Requires every client to pass an HMAC challenge keyed with `secret`
+    /// before any other command.
+    pub fn set_secret(&mut self, secret: &str) {
+        self.secret = Some(secret.as_bytes().into());
+    }
+
+    /// Sets how long a client may stay silent before it is hung up on.
+    pub fn set_client_timeout(&mut self, timeout: Duration) {
+        self.client_timeout = timeout;
+    }
+
+    /// Makes every following client talk TLS.
+    pub fn set_tls(&mut self, config: Arc<ServerConfig>) {
+        self.receiver.tls = Some(config);
+    }
+
+    /// Makes the socket answer discovery probes arriving at `addr` under `name`.
+    pub fn enable_discovery<Addrs>(&mut self, addr: Addrs, name: &str) -> io::Result<()>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let announcement = Announcement {
+            kind: DeviceKind::SmartSocket,
+            name: name.to_owned(),
+            address: self.local_addr()?,
+        };
+        self.responder = Some(Responder::spawn(addr, announcement)?);
+        Ok(())
+    }
+
+    pub fn discovery_addr(&self) -> Option<SocketAddr> {
+        self.responder.as_ref().map(|r| r.local_addr())
+    }
+
+    /// Accepts clients until accepting one fails, so it only ever returns
+    /// an error.
+    pub fn serve(&self) -> Result<(), BindError> {
+        for connection in self.receiver.incoming() {
+            let stream = connection?;
+            let state = self.state.clone();
+            let secret = self.secret.clone();
+            let tls = self.receiver.tls.clone();
+            let timeout = self.client_timeout;
+            thread::spawn(move || {
+                let addr = stream
+                    .peer_addr()
+                    .map(|a| a.to_string())
+                    .unwrap_or_else(|_| "unknown".into());
+                let res = match stream.set_read_timeout(Some(timeout)) {
+                    Err(e) => Err(FrameError::Io(e)),
+                    Ok(()) => match tls {
+                        Some(config) => tls::accept(config, stream)
+                            .map_err(FrameError::Io)
+                            .and_then(|stream| handle_client(stream, state, secret)),
+                        None => handle_client(stream, state, secret),
+                    },
+                };
+                if let Err(e) = res {
+                    println!("got error from client {}: {}", addr, e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_client<S: Read + Write>(
+    stream: S,
+    state: Arc<Mutex<SmartSocketState>>,
+    secret: Option<Arc<[u8]>>,
+) -> Result<(), FrameError> {
+    let mut stream = FramedStream::new(stream);
+    let mut authenticated = secret.is_none();
+    let mut nonce: Option<String> = None;
+    loop {
+        let frame = match stream.read_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(()),
+            // TLS clients often hang up without a close_notify
+            Err(FrameError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            // silent for longer than the client timeout
+            Err(FrameError::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(())
+            }
+            Err(FrameError::Io(e)) => return Err(FrameError::Io(e)),
+            Err(e) => {
+                // the rest of the stream cannot be trusted, answer and hang up
+                stream.write_frame(&ProtocolResponse::Error {
+                    code: ERR_BAD_REQUEST,
+                    message: e.to_string(),
+                })?;
+                return Err(e);
+            }
+        };
+        let response = match (ProtocolCommand::from_str(&frame), &secret) {
+            (Ok(ProtocolCommand::Challenge), Some(_)) => {
+                let challenge = auth::new_nonce();
+                nonce = Some(challenge.clone());
+                ProtocolResponse::Challenge { nonce: challenge }
+            }
+            (Ok(ProtocolCommand::Auth(signature)), Some(secret)) => {
+                // every challenge can be answered only once
+                match nonce.take() {
+                    Some(nonce) if auth::verify(secret, &nonce, &signature) => {
+                        authenticated = true;
+                        ProtocolResponse::Ok
+                    }
+                    _ => ProtocolResponse::Error {
+                        code: ERR_AUTH_FAILED,
+                        message: "authentication failed".into(),
+                    },
+                }
+            }
+            (Ok(ProtocolCommand::Challenge | ProtocolCommand::Auth(_)), None) => {
+                ProtocolResponse::Error {
+                    code: ERR_BAD_REQUEST,
+                    message: "authentication is not enabled".into(),
+                }
+            }
+            (Ok(_), _) if !authenticated => ProtocolResponse::Error {
+                code: ERR_UNAUTHENTICATED,
+                message: "authentication required".into(),
+            },
+            (Ok(command), _) => execute(command, &state),
+            (Err(e), _) => ProtocolResponse::Error {
+                code: ERR_BAD_REQUEST,
+                message: e.to_string(),
+            },
+        };
+        stream.write_frame(&response)?;
+    }
+}
+
+fn execute(command: ProtocolCommand, state: &Mutex<SmartSocketState>) -> ProtocolResponse {
+    let mut state = state.lock().unwrap();
+    match command {
+        ProtocolCommand::Switch => {
+            state.switch();
+            ProtocolResponse::Ok
+        }
+        ProtocolCommand::On => {
+            state.set(true);
+            ProtocolResponse::Ok
+        }
+        ProtocolCommand::Off => {
+            state.set(false);
+            ProtocolResponse::Ok
+        }
+        ProtocolCommand::Status => state.status(),
+        ProtocolCommand::Energy => state.energy(),
+        ProtocolCommand::Challenge | ProtocolCommand::Auth(_) => ProtocolResponse::Error {
+            code: ERR_BAD_REQUEST,
+            message: "unexpected authentication command".into(),
+        },
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum BindError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
+    #[error("cannot restore state: {0}")]
+    Store(#[from] StoreError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+
+    use super::*;
+
+    fn send<S: Read + Write>(
+        stream: &mut FramedStream<S>,
+        command: ProtocolCommand,
+    ) -> ProtocolResponse {
+        stream.write_frame(&command).unwrap();
+        stream.read_frame().unwrap().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn test_energy_accumulates_while_on() {
+        let mut state = SmartSocketState::new(LoadProfile::Constant(1800.0));
+        let start = state.updated_at;
+
+        state.update(start + Duration::from_secs(60));
+        assert_eq!(state.energy_wh, 0.0);
+
+        state.set_at(true, start + Duration::from_secs(60));
+        state.update(start + Duration::from_secs(180));
+        assert!((state.energy_wh - 60.0).abs() < 1e-9);
+
+        state.set_at(false, start + Duration::from_secs(240));
+        state.update(start + Duration::from_secs(600));
+        assert!((state.energy_wh - 90.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_restore_persisted_state() {
+        let path =
+            std::env::temp_dir().join(format!("smart-socket-{}.receiver", std::process::id()));
+
+        let mut state = SmartSocketState::restore(&path).unwrap();
+        state.set_profile(LoadProfile::Constant(60.0));
+        state.set(true);
+        drop(state);
+
+        let mut state = SmartSocketState::restore(&path).unwrap();
+        assert_eq!(
+            state.status(),
+            ProtocolResponse::Status {
+                on: true,
+                watts: 60.0
+            }
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_polls_do_not_write_state() {
+        let path = std::env::temp_dir().join(format!("smart-socket-{}.polls", std::process::id()));
+        let stored_energy = || StoredState::load(&path).unwrap().unwrap().energy_wh;
+
+        let mut state = SmartSocketState::restore(&path).unwrap();
+        state.set_profile(LoadProfile::Constant(3_600_000.0));
+        state.set(true);
+        thread::sleep(Duration::from_millis(10));
+        state.status();
+        state.energy();
+        assert_eq!(stored_energy(), 0.0);
+
+        drop(state);
+        assert!(stored_energy() > 0.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_serve_concurrent_clients() {
+        let server = SmartSocketServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut first = FramedStream::new(TcpStream::connect(addr).unwrap());
+        let mut second = FramedStream::new(TcpStream::connect(addr).unwrap());
+
+        let off = ProtocolResponse::Status {
+            on: false,
+            watts: 0.0,
+        };
+        let on = ProtocolResponse::Status {
+            on: true,
+            watts: 2.0,
+        };
+        assert_eq!(send(&mut second, ProtocolCommand::Status), off);
+        assert_eq!(
+            send(&mut first, ProtocolCommand::Switch),
+            ProtocolResponse::Ok
+        );
+        assert_eq!(send(&mut second, ProtocolCommand::Status), on);
+        assert_eq!(
+            send(&mut second, ProtocolCommand::Switch),
+            ProtocolResponse::Ok
+        );
+        assert_eq!(send(&mut first, ProtocolCommand::Status), off);
+    }
+
+    #[test]
+    fn test_on_off_are_idempotent() {
+        let mut state = SmartSocketState::default();
+        state.set(true);
+        state.set(true);
+        assert!(state.is_on);
+        state.set(false);
+        state.set(false);
+        assert!(!state.is_on);
+        state.switch();
+        assert!(state.is_on);
+    }
+
+    #[test]
+    fn test_tls_connection() {
+        let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+        let cert_pem = cert.serialize_pem().unwrap();
+        let key_pem = cert.serialize_private_key_pem();
+
+        let config = tls::server_config_from_pem(cert_pem.as_bytes(), key_pem.as_bytes()).unwrap();
+        let server =
+            SmartSocketServer::new(SmartSocketReceiver::bind_tls("127.0.0.1:0", config).unwrap());
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let client = tls::client_config_from_pem(cert_pem.as_bytes()).unwrap();
+        let connection =
+            tls::rustls::ClientConnection::new(client, "127.0.0.1".try_into().unwrap()).unwrap();
+        let mut stream = FramedStream::new(tls::rustls::StreamOwned::new(
+            connection,
+            TcpStream::connect(addr).unwrap(),
+        ));
+        assert_eq!(
+            send(&mut stream, ProtocolCommand::Switch),
+            ProtocolResponse::Ok
+        );
+        assert!(matches!(
+            send(&mut stream, ProtocolCommand::Status),
+            ProtocolResponse::Status { on: true, .. }
+        ));
+
+        // a plain TCP client cannot talk to it
+        let mut plain = FramedStream::new(TcpStream::connect(addr).unwrap());
+        plain.write_frame(&ProtocolCommand::Status).unwrap();
+        assert!(!matches!(plain.read_frame(), Ok(Some(_))));
+    }
+
+    #[test]
+    fn test_authentication() {
+        let mut server = SmartSocketServer::bind("127.0.0.1:0").unwrap();
+        server.set_secret("secret");
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut stream = FramedStream::new(TcpStream::connect(addr).unwrap());
+        let code = |response| match response {
+            ProtocolResponse::Error { code, .. } => code,
+            other => panic!("expected error, got {:?}", other),
+        };
+        assert_eq!(
+            code(send(&mut stream, ProtocolCommand::Switch)),
+            ERR_UNAUTHENTICATED
+        );
+
+        let nonce = match send(&mut stream, ProtocolCommand::Challenge) {
+            ProtocolResponse::Challenge { nonce } => nonce,
+            other => panic!("expected challenge, got {:?}", other),
+        };
+        let wrong = auth::sign(b"guess", &nonce);
+        assert_eq!(
+            code(send(&mut stream, ProtocolCommand::Auth(wrong))),
+            ERR_AUTH_FAILED
+        );
+        // the nonce is spent after a failed attempt
+        let right = auth::sign(b"secret", &nonce);
+        assert_eq!(
+            code(send(&mut stream, ProtocolCommand::Auth(right))),
+            ERR_AUTH_FAILED
+        );
+
+        let nonce = match send(&mut stream, ProtocolCommand::Challenge) {
+            ProtocolResponse::Challenge { nonce } => nonce,
+            other => panic!("expected challenge, got {:?}", other),
+        };
+        let right = auth::sign(b"secret", &nonce);
+        assert_eq!(
+            send(&mut stream, ProtocolCommand::Auth(right)),
+            ProtocolResponse::Ok
+        );
+        assert_eq!(
+            send(&mut stream, ProtocolCommand::Switch),
+            ProtocolResponse::Ok
+        );
+    }
+
+    #[test]
+    fn test_hang_up_on_silent_client() {
+        let mut server = SmartSocketServer::bind("127.0.0.1:0").unwrap();
+        server.set_client_timeout(Duration::from_millis(100));
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_unknown_command_keeps_connection() {
+        let server = SmartSocketServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut stream = FramedStream::new(TcpStream::connect(addr).unwrap());
+        stream.write_frame(&"explode").unwrap();
+        match stream.read_frame().unwrap().unwrap().parse().unwrap() {
+            ProtocolResponse::Error { code, .. } => assert_eq!(code, ERR_BAD_REQUEST),
+            other => panic!("expected error, got {:?}", other),
+        }
+        assert_eq!(
+            send(&mut stream, ProtocolCommand::Switch),
+            ProtocolResponse::Ok
+        );
+    }
 }
@@ -0,0 +1,108 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::load::{LoadProfile, LoadProfileError};
+
+/// The part of a socket's state that survives a restart.
+///
+/// It is kept as `key=value` lines:
+///
+/// ```text
+/// on=true
+/// energy_wh=12.5
+/// profile=constant:60
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredState {
+    pub is_on: bool,
+    pub energy_wh: f64,
+    pub profile: LoadProfile,
+}
+
+impl StoredState {
+    /// Returns `None` if nothing has been stored at `path` yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, StoreError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut state = StoredState {
+            is_on: false,
+            energy_wh: 0.0,
+            profile: LoadProfile::default(),
+        };
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let invalid = || StoreError::InvalidLine(line.to_owned());
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            match key.trim() {
+                "on" => state.is_on = value.trim().parse().map_err(|_| invalid())?,
+                "energy_wh" => state.energy_wh = value.trim().parse().map_err(|_| invalid())?,
+                "profile" => state.profile = value.parse()?,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Some(state))
+    }
+
+    /// Replaces the file at `path` in one step, so a crash never leaves it
+    /// half written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        // state.json and state.bin must not share a temporary file
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut file = fs::File::create(&tmp)?;
+        writeln!(file, "on={}", self.is_on)?;
+        writeln!(file, "energy_wh={}", self.energy_wh)?;
+        writeln!(file, "profile={}", self.profile)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid state line: {0}")]
+    InvalidLine(String),
+    #[error("invalid stored profile: {0}")]
+    Profile(#[from] LoadProfileError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("smart-socket-{}.state", std::process::id()));
+        assert_eq!(StoredState::load(&path).unwrap(), None);
+
+        let state = StoredState {
+            is_on: true,
+            energy_wh: 12.5,
+            profile: LoadProfile::RandomWalk {
+                min: 1.0,
+                max: 10.0,
+                step: 0.5,
+            },
+        };
+        state.save(&path).unwrap();
+        assert_eq!(StoredState::load(&path).unwrap(), Some(state));
+
+        fs::write(&path, "on=maybe\n").unwrap();
+        assert!(matches!(
+            StoredState::load(&path),
+            Err(StoreError::InvalidLine(_))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
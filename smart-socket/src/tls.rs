@@ -0,0 +1,109 @@
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use rustls::{ServerConnection, StreamOwned};
+use thiserror::Error;
+
+pub use rustls;
+
+/// Server side of a TLS connection to a smart socket.
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// Builds a server config from a PEM certificate chain and a PEM private key.
+pub fn server_config<P: AsRef<Path>>(cert: P, key: P) -> Result<Arc<ServerConfig>, TlsError> {
+    server_config_from_pem(&fs::read(cert)?, &fs::read(key)?)
+}
+
+pub fn server_config_from_pem(cert: &[u8], key: &[u8]) -> Result<Arc<ServerConfig>, TlsError> {
+    let certs = read_certs(cert)?;
+    let key = read_key(key)?;
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// Builds a client config trusting only the PEM certificates in `ca`,
+/// which for a self-signed socket is its own certificate.
+pub fn client_config<P: AsRef<Path>>(ca: P) -> Result<Arc<ClientConfig>, TlsError> {
+    client_config_from_pem(&fs::read(ca)?)
+}
+
+pub fn client_config_from_pem(ca: &[u8]) -> Result<Arc<ClientConfig>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(ca)? {
+        roots.add(&cert)?;
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Wraps an accepted connection. The handshake happens on the first read.
+pub fn accept(config: Arc<ServerConfig>, stream: TcpStream) -> io::Result<TlsStream> {
+    let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+fn read_certs(pem: &[u8]) -> Result<Vec<Certificate>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(pem))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate);
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(pem: &[u8]) -> Result<PrivateKey, TlsError> {
+    let mut reader = BufReader::new(pem);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => (),
+        }
+    }
+    Err(TlsError::NoPrivateKey)
+}
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("TLS error: {0}")]
+    Rustls(#[from] rustls::Error),
+    #[error("no certificate found")]
+    NoCertificate,
+    #[error("no private key found")]
+    NoPrivateKey,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configs_from_self_signed_cert() {
+        let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+        let cert_pem = cert.serialize_pem().unwrap();
+        let key_pem = cert.serialize_private_key_pem();
+
+        server_config_from_pem(cert_pem.as_bytes(), key_pem.as_bytes()).unwrap();
+        client_config_from_pem(cert_pem.as_bytes()).unwrap();
+        assert!(matches!(
+            server_config_from_pem(cert_pem.as_bytes(), cert_pem.as_bytes()),
+            Err(TlsError::NoPrivateKey)
+        ));
+        assert!(matches!(
+            client_config_from_pem(key_pem.as_bytes()),
+            Err(TlsError::NoCertificate)
+        ));
+    }
+}
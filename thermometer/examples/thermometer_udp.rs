@@ -1,15 +1,86 @@
+use std::io::{self, BufRead};
 use std::thread;
 use std::time::Duration;
 
 use thermometer::sender::*;
+use thermometer::simulation::Generator;
+
+const USAGE: &str = "usage: thermometer_udp <src addr> <remote addr> <name> <value | generator> \
+                     [discovery addr] [--kind <kind>] [--unit <unit>] [--interval <ms>] [--jitter <0..1>] \
+                     [--loss <0..1>]";
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let src_addr = std::env::args().nth(1).unwrap();
-    let remote_addr = std::env::args().nth(2).unwrap();
-    let name = std::env::args().nth(3).unwrap();
-    let value: f64 = std::env::args().nth(4).unwrap().parse().unwrap();
+    let mut args = std::env::args().skip(1);
+    let src_addr = args.next().ok_or(USAGE)?;
+    let remote_addr = args.next().ok_or(USAGE)?;
+    let name = args.next().ok_or(USAGE)?;
+    // a plain number is a constant value, anything else a generator,
+    // e.g. sine:20:5:86400, random:18:24:0.1 or replay:temperature.csv
+    let generator = args.next().ok_or(USAGE)?;
+    let generator = match generator.parse::<f64>() {
+        Ok(value) => Generator::Constant(value),
+        Err(_) => generator.parse()?,
+    };
+
+    let mut config = SenderConfig {
+        generator,
+        ..SenderConfig::default()
+    };
+    // optional address to answer discovery probes on, e.g. 0.0.0.0:10710
+    let mut discovery_addr = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval" => {
+                config.interval = Duration::from_millis(args.next().ok_or(USAGE)?.parse()?)
+            }
+            // temperature, humidity, pressure, co2 or battery
+            "--kind" => config.kind = args.next().ok_or(USAGE)?.parse()?,
+            // C, F, K, %, hPa or ppm, the kind's default if not given
+            "--unit" => config.unit = Some(args.next().ok_or(USAGE)?.parse()?),
+            "--jitter" => config.jitter = args.next().ok_or(USAGE)?.parse()?,
+            "--loss" => config.loss = args.next().ok_or(USAGE)?.parse()?,
+            _ if arg.starts_with("--") => return Err(USAGE.into()),
+            _ if discovery_addr.is_none() => discovery_addr = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
 
-    Sender::new(src_addr, remote_addr, name, value)?;
+    let mut sender = Sender::with_config(src_addr, remote_addr, name, config)?;
+    if let Some(discovery_addr) = discovery_addr {
+        sender.enable_discovery(discovery_addr)?;
+    }
+
+    // commands on stdin: value <value>, interval <ms>, remote <addr> or stop
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let res: Result<(), Box<dyn std::error::Error>> = match line.trim().split_once(' ') {
+            Some(("value", value)) => value
+                .parse()
+                .map(|v| sender.set_value(v))
+                .map_err(Into::into),
+            Some(("interval", ms)) => match ms.parse() {
+                Ok(0) => Err("interval must not be zero".into()),
+                Ok(ms) => {
+                    sender.set_interval(Duration::from_millis(ms));
+                    Ok(())
+                }
+                Err(e) => Err(e.into()),
+            },
+            Some(("remote", addr)) => {
+                sender.set_remote(addr.trim().to_owned());
+                Ok(())
+            }
+            None if line.trim() == "stop" => {
+                sender.stop();
+                return Ok(());
+            }
+            _ => Err("unknown command".into()),
+        };
+        if let Err(e) = res {
+            println!("{}: {}", line.trim(), e);
+        }
+    }
+    // without stdin there is no one to stop the thermometer
     loop {
         thread::sleep(Duration::new(1, 0));
     }
@@ -0,0 +1,244 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::datagram::{DatagramError, MeasurementKind, Unit};
+use crate::sender::SenderConfig;
+use crate::simulation::{Generator, GeneratorError};
+
+/// Sensors without a `source` share a socket bound to this address.
+pub const DEFAULT_SOURCE: &str = "0.0.0.0:0";
+
+/// A simulated sensor sending measurements of one kind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorConfig {
+    pub name: String,
+    /// Address to bind to. Sensors with the same source send from the same socket.
+    pub source: String,
+    pub remote: String,
+    pub sender: SenderConfig,
+}
+
+/// The sensors one `thermometer` process runs, one section per sensor:
+///
+/// ```text
+/// # lines starting with # are comments
+/// [thermometer on the wall]
+/// remote=127.0.0.1:11701
+/// kind=temperature
+/// unit=C
+/// generator=sine:21:2:86400
+/// interval_ms=500
+/// jitter=0.1
+/// loss=0.05
+/// source=0.0.0.0:11700
+/// ```
+///
+/// `remote` is required, the rest defaults to `SenderConfig::default()`
+/// and `DEFAULT_SOURCE`. `unit` is the one the generator's values are in,
+/// e.g. `F` for a thermometer reporting Fahrenheit. A sensor measuring
+/// several kinds has a section per kind, all with the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub sensors: Vec<SensorConfig>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        fs::read_to_string(path)?.parse()
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sensors: Vec<(SensorConfig, Option<String>)> = Vec::new();
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || ConfigError::InvalidLine(idx + 1);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err(invalid());
+                }
+                sensors.push((
+                    SensorConfig {
+                        name: name.to_owned(),
+                        source: DEFAULT_SOURCE.to_owned(),
+                        remote: String::new(),
+                        sender: SenderConfig::default(),
+                    },
+                    None,
+                ));
+                continue;
+            }
+
+            let (sensor, remote) = sensors.last_mut().ok_or_else(invalid)?;
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            let value = value.trim();
+            let fraction = || {
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| (0.0..=1.0).contains(v))
+                    .ok_or_else(invalid)
+            };
+            match key.trim() {
+                "remote" => *remote = Some(value.to_owned()),
+                "source" => sensor.source = value.to_owned(),
+                "kind" => sensor.sender.kind = value.parse()?,
+                "unit" => sensor.sender.unit = Some(value.parse()?),
+                "generator" => sensor.sender.generator = value.parse::<Generator>()?,
+                "interval_ms" => {
+                    sensor.sender.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|ms| *ms > 0)
+                        .map(Duration::from_millis)
+                        .ok_or_else(invalid)?
+                }
+                "jitter" => sensor.sender.jitter = fraction()?,
+                "loss" => sensor.sender.loss = fraction()?,
+                _ => return Err(invalid()),
+            }
+        }
+
+        let sensors = sensors
+            .into_iter()
+            .map(|(sensor, remote)| match remote {
+                Some(remote) => Ok(SensorConfig { remote, ..sensor }),
+                None => Err(ConfigError::MissingRemote(sensor.name)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if sensors.is_empty() {
+            return Err(ConfigError::NoSensors);
+        }
+        for (idx, sensor) in sensors.iter().enumerate() {
+            let (kind, unit) = (sensor.sender.kind, sensor.sender.unit());
+            if !kind.accepts(unit) {
+                return Err(ConfigError::UnitMismatch(sensor.name.clone(), kind, unit));
+            }
+            if sensors[..idx]
+                .iter()
+                .any(|s| s.name == sensor.name && s.sender.kind == sensor.sender.kind)
+            {
+                return Err(ConfigError::DuplicateSensor(
+                    sensor.name.clone(),
+                    sensor.sender.kind,
+                ));
+            }
+        }
+        Ok(Self { sensors })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid config line {0}")]
+    InvalidLine(usize),
+    #[error("no remote address for sensor {0:?}")]
+    MissingRemote(String),
+    #[error("no sensors configured")]
+    NoSensors,
+    #[error("sensor {0:?} measures {1} twice")]
+    DuplicateSensor(String, MeasurementKind),
+    #[error("sensor {0:?} cannot measure {1} in {2}")]
+    UnitMismatch(String, MeasurementKind, Unit),
+    #[error("{0}")]
+    Kind(#[from] DatagramError),
+    #[error("{0}")]
+    Generator(#[from] GeneratorError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = "
+            # the living room
+            [thermometer on the wall]
+            remote = 127.0.0.1:11701
+            generator = sine:21:2:86400
+            interval_ms = 500
+            loss = 0.05
+
+            [balcony]
+            remote=127.0.0.1:11701
+            source=127.0.0.1:11700
+            unit=F
+
+            [balcony]
+            remote=127.0.0.1:11701
+            kind=humidity
+        "
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.sensors.len(), 3);
+        let wall = &config.sensors[0];
+        assert_eq!(wall.name, "thermometer on the wall");
+        assert_eq!(wall.source, DEFAULT_SOURCE);
+        assert_eq!(wall.sender.generator.to_string(), "sine:21:2:86400");
+        assert_eq!(wall.sender.interval, Duration::from_millis(500));
+        assert_eq!((wall.sender.jitter, wall.sender.loss), (0.0, 0.05));
+        let balcony = &config.sensors[1];
+        assert_eq!(balcony.source, "127.0.0.1:11700");
+        assert_eq!(balcony.sender.interval, SenderConfig::default().interval);
+        assert_eq!(balcony.sender.kind, MeasurementKind::Temperature);
+        assert_eq!(balcony.sender.unit(), Unit::Fahrenheit);
+        assert_eq!(config.sensors[2].sender.unit(), Unit::Percent);
+        assert_eq!(config.sensors[2].sender.kind, MeasurementKind::Humidity);
+    }
+
+    #[test]
+    fn test_parse_invalid_config() {
+        assert!(matches!("".parse::<Config>(), Err(ConfigError::NoSensors)));
+        assert!(matches!(
+            "remote=127.0.0.1:11701".parse::<Config>(),
+            Err(ConfigError::InvalidLine(1))
+        ));
+        assert!(matches!(
+            "[a]\ngenerator=constant:1".parse::<Config>(),
+            Err(ConfigError::MissingRemote(name)) if name == "a"
+        ));
+        assert!(matches!(
+            "[a]\nremote=x\n[a]\nremote=y".parse::<Config>(),
+            Err(ConfigError::DuplicateSensor(
+                _,
+                MeasurementKind::Temperature
+            ))
+        ));
+        assert!(matches!(
+            "[a]\nremote=x\nkind=wind".parse::<Config>(),
+            Err(ConfigError::Kind(_))
+        ));
+        assert!(matches!(
+            "[a]\nremote=x\nkind=humidity\nunit=K".parse::<Config>(),
+            Err(ConfigError::UnitMismatch(..))
+        ));
+        assert!(matches!(
+            "[a]\nremote=x\nunit=mph".parse::<Config>(),
+            Err(ConfigError::Kind(_))
+        ));
+        assert!(matches!(
+            "[a]\nremote=x\nloss=2".parse::<Config>(),
+            Err(ConfigError::InvalidLine(3))
+        ));
+        assert!(matches!(
+            "[a]\nremote=x\ngenerator=solar:1".parse::<Config>(),
+            Err(ConfigError::Generator(_))
+        ));
+    }
+}
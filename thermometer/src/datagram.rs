@@ -0,0 +1,517 @@
+use std::fmt;
+use std::str::{self, FromStr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Largest payload a UDP datagram can carry, so no reading is ever cut short.
+pub const MAX_DATAGRAM_LEN: usize = 65_507;
+
+/// Version of the telemetry format `Telemetry::encode` writes.
+pub const VERSION: u32 = 1;
+
+const SEPARATOR: &str = ":\t";
+
+/// What a sensor measures. A sensor may send several kinds under one name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MeasurementKind {
+    Temperature,
+    /// Relative humidity.
+    Humidity,
+    /// Atmospheric pressure.
+    Pressure,
+    Co2,
+    /// Charge left in the sensor's battery.
+    Battery,
+}
+
+impl MeasurementKind {
+    pub const ALL: [MeasurementKind; 5] = [
+        MeasurementKind::Temperature,
+        MeasurementKind::Humidity,
+        MeasurementKind::Pressure,
+        MeasurementKind::Co2,
+        MeasurementKind::Battery,
+    ];
+
+    /// The unit a sender uses unless told otherwise.
+    pub fn unit(self) -> Unit {
+        match self {
+            MeasurementKind::Temperature => Unit::Celsius,
+            MeasurementKind::Humidity | MeasurementKind::Battery => Unit::Percent,
+            MeasurementKind::Pressure => Unit::Hectopascal,
+            MeasurementKind::Co2 => Unit::Ppm,
+        }
+    }
+
+    /// Whether a value of this kind can be given in `unit`.
+    pub fn accepts(self, unit: Unit) -> bool {
+        match self {
+            MeasurementKind::Temperature => unit.is_temperature(),
+            _ => self.unit() == unit,
+        }
+    }
+}
+
+impl fmt::Display for MeasurementKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeasurementKind::Temperature => write!(f, "temperature"),
+            MeasurementKind::Humidity => write!(f, "humidity"),
+            MeasurementKind::Pressure => write!(f, "pressure"),
+            MeasurementKind::Co2 => write!(f, "co2"),
+            MeasurementKind::Battery => write!(f, "battery"),
+        }
+    }
+}
+
+impl FromStr for MeasurementKind {
+    type Err = DatagramError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MeasurementKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s.trim())
+            .ok_or_else(|| DatagramError::UnknownKind(s.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Unit {
+    #[serde(rename = "C")]
+    Celsius,
+    #[serde(rename = "F")]
+    Fahrenheit,
+    #[serde(rename = "K")]
+    Kelvin,
+    #[serde(rename = "%")]
+    Percent,
+    #[serde(rename = "hPa")]
+    Hectopascal,
+    /// Parts per million.
+    #[serde(rename = "ppm")]
+    Ppm,
+}
+
+impl Unit {
+    pub const ALL: [Unit; 6] = [
+        Unit::Celsius,
+        Unit::Fahrenheit,
+        Unit::Kelvin,
+        Unit::Percent,
+        Unit::Hectopascal,
+        Unit::Ppm,
+    ];
+
+    pub fn is_temperature(self) -> bool {
+        matches!(self, Unit::Celsius | Unit::Fahrenheit | Unit::Kelvin)
+    }
+
+    /// Converts `value` given in this unit to `to`, `None` if the two
+    /// units do not measure the same thing.
+    pub fn convert(self, value: f64, to: Unit) -> Option<f64> {
+        if self == to {
+            return Some(value);
+        }
+        let celsius = match self {
+            Unit::Celsius => value,
+            Unit::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+            Unit::Kelvin => value - 273.15,
+            _ => return None,
+        };
+        match to {
+            Unit::Celsius => Some(celsius),
+            Unit::Fahrenheit => Some(celsius * 9.0 / 5.0 + 32.0),
+            Unit::Kelvin => Some(celsius + 273.15),
+            _ => None,
+        }
+    }
+
+    /// The name used in telemetry datagrams, e.g. `C` or `hPa`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Unit::Celsius => "C",
+            Unit::Fahrenheit => "F",
+            Unit::Kelvin => "K",
+            Unit::Percent => "%",
+            Unit::Hectopascal => "hPa",
+            Unit::Ppm => "ppm",
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unit::Celsius => write!(f, "°C"),
+            Unit::Fahrenheit => write!(f, "°F"),
+            Unit::Kelvin => write!(f, "K"),
+            Unit::Percent => write!(f, "%"),
+            Unit::Hectopascal => write!(f, "hPa"),
+            Unit::Ppm => write!(f, "ppm"),
+        }
+    }
+}
+
+/// Parses either the telemetry name, e.g. `F`, or the displayed one, e.g. `°F`.
+impl FromStr for Unit {
+    type Err = DatagramError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Unit::ALL
+            .into_iter()
+            .find(|unit| unit.code() == s || unit.to_string() == s)
+            .ok_or_else(|| DatagramError::UnknownUnit(s.to_owned()))
+    }
+}
+
+/// The units temperatures can be shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl From<TemperatureUnit> for Unit {
+    fn from(unit: TemperatureUnit) -> Self {
+        match unit {
+            TemperatureUnit::Celsius => Unit::Celsius,
+            TemperatureUnit::Fahrenheit => Unit::Fahrenheit,
+            TemperatureUnit::Kelvin => Unit::Kelvin,
+        }
+    }
+}
+
+impl TryFrom<Unit> for TemperatureUnit {
+    type Error = DatagramError;
+
+    fn try_from(unit: Unit) -> Result<Self, Self::Error> {
+        match unit {
+            Unit::Celsius => Ok(TemperatureUnit::Celsius),
+            Unit::Fahrenheit => Ok(TemperatureUnit::Fahrenheit),
+            Unit::Kelvin => Ok(TemperatureUnit::Kelvin),
+            _ => Err(DatagramError::NotTemperature(unit)),
+        }
+    }
+}
+
+impl fmt::Display for TemperatureUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Unit::from(*self).fmt(f)
+    }
+}
+
+/// Parses the same names as `Unit`, but only °C, °F and K.
+impl FromStr for TemperatureUnit {
+    type Err = DatagramError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Unit>()?.try_into()
+    }
+}
+
+/// A measurement in the versioned telemetry format, a JSON object:
+///
+/// ```text
+/// {"v":1,"device":"balcony","kind":"temperature","unit":"C","value":-3.5,"seq":42,"ts":1650000000123}
+/// ```
+///
+/// `seq` grows by one with every datagram a sender sends, `ts` is the send
+/// time in milliseconds since the Unix epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Telemetry {
+    pub device: String,
+    pub kind: MeasurementKind,
+    pub unit: Unit,
+    pub value: f64,
+    pub seq: u64,
+    pub sent_at: SystemTime,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    v: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireV1 {
+    v: u32,
+    device: String,
+    kind: MeasurementKind,
+    unit: Unit,
+    value: f64,
+    seq: u64,
+    ts: u64,
+}
+
+impl Telemetry {
+    pub fn encode(&self) -> Vec<u8> {
+        let wire = WireV1 {
+            v: VERSION,
+            device: self.device.clone(),
+            kind: self.kind,
+            unit: self.unit,
+            value: self.value,
+            seq: self.seq,
+            ts: self
+                .sent_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        };
+        serde_json::to_vec(&wire).expect("telemetry is always serializable")
+    }
+
+    fn decode(datagram: &[u8]) -> Result<Self, DatagramError> {
+        let header: Header = serde_json::from_slice(datagram)
+            .map_err(|e| DatagramError::Malformed(e.to_string()))?;
+        if header.v != VERSION {
+            return Err(DatagramError::UnsupportedVersion(header.v));
+        }
+        let wire: WireV1 = serde_json::from_slice(datagram)
+            .map_err(|e| DatagramError::Malformed(e.to_string()))?;
+        if wire.device.trim().is_empty() {
+            return Err(DatagramError::Malformed("empty device id".into()));
+        }
+        if !wire.value.is_finite() {
+            return Err(DatagramError::InvalidValue(wire.value.to_string()));
+        }
+        if !wire.kind.accepts(wire.unit) {
+            return Err(DatagramError::Malformed(format!(
+                "{} cannot be measured in {}",
+                wire.kind, wire.unit
+            )));
+        }
+        Ok(Self {
+            device: wire.device,
+            kind: wire.kind,
+            unit: wire.unit,
+            value: wire.value,
+            seq: wire.seq,
+            sent_at: UNIX_EPOCH + Duration::from_millis(wire.ts),
+        })
+    }
+}
+
+/// Anything a receiver may get from a thermometer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datagram {
+    /// The original text format, a temperature in degrees Celsius.
+    Legacy(Reading),
+    Telemetry(Telemetry),
+}
+
+impl Datagram {
+    pub fn device(&self) -> &str {
+        match self {
+            Datagram::Legacy(reading) => &reading.name,
+            Datagram::Telemetry(telemetry) => &telemetry.device,
+        }
+    }
+
+    pub fn value(&self) -> f64 {
+        match self {
+            Datagram::Legacy(reading) => reading.value,
+            Datagram::Telemetry(telemetry) => telemetry.value,
+        }
+    }
+}
+
+/// Tells the telemetry format from the legacy one by the leading `{`.
+pub fn parse(datagram: &[u8]) -> Result<Datagram, DatagramError> {
+    if datagram.trim_ascii_start().starts_with(b"{") {
+        Telemetry::decode(datagram).map(Datagram::Telemetry)
+    } else {
+        parse_legacy(datagram).map(Datagram::Legacy)
+    }
+}
+
+/// A single reading in the legacy text format: `<name>:\t<value>`.
+///
+/// The name may contain anything but line breaks, the value is a decimal
+/// number and may be negative or fractional, e.g. `balcony:\t-3.5`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub name: String,
+    pub value: f64,
+}
+
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.name, SEPARATOR, self.value)
+    }
+}
+
+/// Parses a legacy datagram. Trailing whitespace, e.g. a line break, is ignored.
+pub fn parse_legacy(datagram: &[u8]) -> Result<Reading, DatagramError> {
+    let s = str::from_utf8(datagram).map_err(|_| DatagramError::InvalidUtf8)?;
+    let s = s.trim_end();
+    let (name, value) = s
+        .rsplit_once(SEPARATOR)
+        .ok_or_else(|| DatagramError::Malformed(s.to_owned()))?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(['\r', '\n']) {
+        return Err(DatagramError::Malformed(s.to_owned()));
+    }
+    let value = match value.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => value,
+        _ => return Err(DatagramError::InvalidValue(value.to_owned())),
+    };
+    Ok(Reading {
+        name: name.to_owned(),
+        value,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum DatagramError {
+    #[error("datagram is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("malformed datagram: {0:?}")]
+    Malformed(String),
+    #[error("invalid value: {0:?}")]
+    InvalidValue(String),
+    #[error("unsupported telemetry version {0}")]
+    UnsupportedVersion(u32),
+    #[error("unknown measurement kind: {0:?}")]
+    UnknownKind(String),
+    #[error("unknown unit: {0:?}")]
+    UnknownUnit(String),
+    #[error("{0} is not a temperature unit")]
+    NotTemperature(Unit),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reading() {
+        let reading = parse_legacy(b"thermometer on the wall:\t23.5").unwrap();
+        assert_eq!(reading.name, "thermometer on the wall");
+        assert_eq!(reading.value, 23.5);
+
+        assert_eq!(parse_legacy(b"balcony:\t-3\n").unwrap().value, -3.0);
+
+        let long_name = "a very long thermometer name ".repeat(10);
+        let reading = Reading {
+            name: long_name.trim().to_owned(),
+            value: 0.25,
+        };
+        assert_eq!(
+            parse(reading.to_string().as_bytes()).unwrap(),
+            Datagram::Legacy(reading)
+        );
+    }
+
+    #[test]
+    fn test_telemetry_roundtrip() {
+        let telemetry = Telemetry {
+            device: "balcony".into(),
+            kind: MeasurementKind::Temperature,
+            unit: Unit::Celsius,
+            value: -3.5,
+            seq: 42,
+            sent_at: UNIX_EPOCH + Duration::from_millis(1_650_000_000_123),
+        };
+        let encoded = telemetry.encode();
+        assert_eq!(
+            str::from_utf8(&encoded).unwrap(),
+            r#"{"v":1,"device":"balcony","kind":"temperature","unit":"C","value":-3.5,"seq":42,"ts":1650000000123}"#
+        );
+        assert_eq!(parse(&encoded).unwrap(), Datagram::Telemetry(telemetry));
+
+        assert!(matches!(
+            parse(br#"{"v":2,"device":"balcony"}"#),
+            Err(DatagramError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            parse(br#"{"v":1,"device":"balcony"}"#),
+            Err(DatagramError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_measurement_kinds() {
+        for kind in MeasurementKind::ALL {
+            assert_eq!(kind.to_string().parse::<MeasurementKind>().unwrap(), kind);
+            let telemetry = Telemetry {
+                device: "weather station".into(),
+                kind,
+                unit: kind.unit(),
+                value: 42.0,
+                seq: 0,
+                sent_at: UNIX_EPOCH,
+            };
+            assert_eq!(
+                parse(&telemetry.encode()).unwrap(),
+                Datagram::Telemetry(telemetry)
+            );
+        }
+        assert!("wind".parse::<MeasurementKind>().is_err());
+
+        let humidity =
+            br#"{"v":1,"device":"d","kind":"humidity","unit":"%","value":45,"seq":0,"ts":0}"#;
+        assert!(parse(humidity).is_ok());
+        let in_celsius =
+            br#"{"v":1,"device":"d","kind":"humidity","unit":"C","value":45,"seq":0,"ts":0}"#;
+        assert!(matches!(
+            parse(in_celsius),
+            Err(DatagramError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_units() {
+        for unit in Unit::ALL {
+            assert_eq!(unit.code().parse::<Unit>().unwrap(), unit);
+            assert_eq!(unit.to_string().parse::<Unit>().unwrap(), unit);
+        }
+        assert!("kelvin".parse::<Unit>().is_err());
+        assert_eq!(
+            "°F".parse::<TemperatureUnit>().unwrap(),
+            TemperatureUnit::Fahrenheit
+        );
+        assert!(matches!(
+            "%".parse::<TemperatureUnit>(),
+            Err(DatagramError::NotTemperature(Unit::Percent))
+        ));
+
+        let close = |a: Option<f64>, b: f64| (a.unwrap() - b).abs() < 1e-9;
+        assert!(close(Unit::Celsius.convert(100.0, Unit::Fahrenheit), 212.0));
+        assert!(close(Unit::Fahrenheit.convert(-40.0, Unit::Celsius), -40.0));
+        assert!(close(Unit::Kelvin.convert(0.0, Unit::Fahrenheit), -459.67));
+        assert_eq!(Unit::Percent.convert(45.0, Unit::Percent), Some(45.0));
+        assert_eq!(Unit::Celsius.convert(20.0, Unit::Percent), None);
+
+        let fahrenheit =
+            br#"{"v":1,"device":"d","kind":"temperature","unit":"F","value":70,"seq":0,"ts":0}"#;
+        assert!(parse(fahrenheit).is_ok());
+        assert!(!MeasurementKind::Humidity.accepts(Unit::Kelvin));
+    }
+
+    #[test]
+    fn test_parse_invalid_datagrams() {
+        assert!(matches!(
+            parse(b"\xff\xfe:\t1"),
+            Err(DatagramError::InvalidUtf8)
+        ));
+        assert!(matches!(
+            parse(b"no value"),
+            Err(DatagramError::Malformed(_))
+        ));
+        assert!(matches!(parse(b":\t1"), Err(DatagramError::Malformed(_))));
+        assert!(matches!(
+            parse(b"t:\twarm"),
+            Err(DatagramError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            parse(b"t:\tNaN"),
+            Err(DatagramError::InvalidValue(_))
+        ));
+    }
+}
@@ -1 +1,4 @@
+pub mod config;
+pub mod datagram;
 pub mod sender;
+pub mod simulation;
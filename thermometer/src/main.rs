@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use thermometer::config::Config;
+use thermometer::sender::{Sender, SenderStats};
+
+const USAGE: &str = "usage: thermometer <config> [--duration <secs>]";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut path = None;
+    // stop on its own after this long instead of waiting for Ctrl-C
+    let mut duration = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--duration" => {
+                duration = Some(Duration::from_secs(args.next().ok_or(USAGE)?.parse()?))
+            }
+            _ if arg.starts_with("--") || path.is_some() => return Err(USAGE.into()),
+            _ => path = Some(arg),
+        }
+    }
+    let config = Config::load(path.ok_or(USAGE)?)?;
+
+    let mut sockets: HashMap<String, UdpSocket> = HashMap::new();
+    let mut senders = Vec::with_capacity(config.sensors.len());
+    for sensor in config.sensors {
+        let socket = match sockets.get(&sensor.source) {
+            Some(socket) => socket.try_clone()?,
+            None => {
+                let socket = UdpSocket::bind(&sensor.source)?;
+                sockets.insert(sensor.source.clone(), socket.try_clone()?);
+                socket
+            }
+        };
+        senders.push(Sender::with_socket(
+            socket,
+            sensor.remote,
+            sensor.name,
+            sensor.sender,
+        )?);
+    }
+    println!(
+        "running {} sensors over {} sockets",
+        senders.len(),
+        sockets.len()
+    );
+
+    let (stop_tx, stop_rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = stop_tx.send(());
+    })?;
+    match duration {
+        Some(duration) => {
+            let _ = stop_rx.recv_timeout(duration);
+        }
+        None => {
+            let _ = stop_rx.recv();
+        }
+    }
+
+    for sender in &mut senders {
+        sender.stop();
+    }
+    report(&senders);
+    Ok(())
+}
+
+fn report(senders: &[Sender]) {
+    let width = senders
+        .iter()
+        .map(|s| s.name().chars().count() + s.kind().to_string().len() + 3)
+        .chain(Some("total".len()))
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:<width$}  {:>8}  {:>8}  {:>8}",
+        "sensor",
+        "sent",
+        "lost",
+        "failed",
+        width = width
+    );
+
+    let mut total = SenderStats::default();
+    let row = |name: &str, stats: SenderStats| {
+        println!(
+            "{:<width$}  {:>8}  {:>8}  {:>8}",
+            name,
+            stats.sent,
+            stats.lost,
+            stats.failed,
+            width = width
+        );
+    };
+    for sender in senders {
+        let stats = sender.stats();
+        total.sent += stats.sent;
+        total.lost += stats.lost;
+        total.failed += stats.failed;
+        row(&format!("{} ({})", sender.name(), sender.kind()), stats);
+    }
+    row("total", total);
+}
@@ -1,27 +1,500 @@
-use std::error::Error;
-use std::net::UdpSocket;
-use std::thread;
-use std::time::Duration;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
+use discovery::{Announcement, DeviceKind, Responder};
+use rand::Rng;
+use thiserror::Error;
+
+use crate::datagram::{MeasurementKind, Telemetry, Unit};
+use crate::simulation::Generator;
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+/// `set_interval` raises shorter intervals to this, so the thread never spins.
+pub const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What a simulated sensor sends and how reliably.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SenderConfig {
+    pub kind: MeasurementKind,
+    /// The unit the generator's values are in, `None` for the kind's
+    /// default, e.g. °C for temperatures.
+    pub unit: Option<Unit>,
+    pub generator: Generator,
+    pub interval: Duration,
+    /// Part of the interval, from 0 to 1, by which every pause is randomly
+    /// shortened or lengthened.
+    pub jitter: f64,
+    /// Probability, from 0 to 1, that a datagram is silently not sent.
+    pub loss: f64,
+}
+
+impl Default for SenderConfig {
+    fn default() -> Self {
+        Self {
+            kind: MeasurementKind::Temperature,
+            unit: None,
+            generator: Generator::Constant(0.0),
+            interval: DEFAULT_INTERVAL,
+            jitter: 0.0,
+            loss: 0.0,
+        }
+    }
+}
+
+impl SenderConfig {
+    pub fn unit(&self) -> Unit {
+        self.unit.unwrap_or_else(|| self.kind.unit())
+    }
+
+    /// Checks what the sender relies on: a unit the kind can be measured
+    /// in, a non-zero interval, and jitter and loss from 0 to 1.
+    pub fn validate(&self) -> Result<(), SenderError> {
+        let (kind, unit) = (self.kind, self.unit());
+        if !kind.accepts(unit) {
+            return Err(SenderError::UnitMismatch(kind, unit));
+        }
+        if self.interval.is_zero() {
+            return Err(SenderError::ZeroInterval);
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(SenderError::InvalidJitter(self.jitter));
+        }
+        if !(0.0..=1.0).contains(&self.loss) {
+            return Err(SenderError::InvalidLoss(self.loss));
+        }
+        Ok(())
+    }
+
+    fn pause(&self) -> Duration {
+        if self.jitter == 0.0 {
+            return self.interval;
+        }
+        self.interval
+            .mul_f64(1.0 + self.jitter * rand::thread_rng().gen_range(-1.0..=1.0))
+    }
+
+    fn is_lost(&self) -> bool {
+        self.loss > 0.0 && rand::thread_rng().gen_bool(self.loss)
+    }
+}
+
+#[derive(Debug)]
+struct Settings {
+    config: SenderConfig,
+    remote_addr: String,
+    stopped: bool,
+    /// Set by every update, so the thread sends right away instead of
+    /// finishing the pause under the old settings.
+    changed: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    settings: Mutex<Settings>,
+    wake: Condvar,
+    sent: AtomicU64,
+    lost: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// What happened to the datagrams a sender produced so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SenderStats {
+    pub sent: u64,
+    /// Dropped on purpose to simulate loss, see `SenderConfig::loss`.
+    pub lost: u64,
+    /// The socket refused to send them.
+    pub failed: u64,
+}
+
+/// Sends measurements of one kind in the telemetry format from a thread of
+/// its own, which stops on `stop` or when the sender is dropped.
 #[derive(Debug)]
-pub struct Sender {}
+pub struct Sender {
+    name: String,
+    kind: MeasurementKind,
+    local_addr: SocketAddr,
+    responder: Option<Responder>,
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
 
 impl Sender {
+    /// Sends `value` as a temperature in degrees Celsius every 100ms.
     pub fn new(
         src_addr: String,
         remote_addr: String,
         name: String,
         value: f64,
-    ) -> Result<Self, Box<dyn Error>> {
+    ) -> Result<Self, SenderError> {
+        Self::with_config(
+            src_addr,
+            remote_addr,
+            name,
+            SenderConfig {
+                generator: Generator::Constant(value),
+                ..SenderConfig::default()
+            },
+        )
+    }
+
+    pub fn with_config(
+        src_addr: String,
+        remote_addr: String,
+        name: String,
+        config: SenderConfig,
+    ) -> Result<Self, SenderError> {
         let socket = UdpSocket::bind(src_addr)?;
+        Self::with_socket(socket, remote_addr, name, config)
+    }
+
+    /// Sends from `socket`, which may be a `try_clone` of one that other
+    /// senders use as well.
+    pub fn with_socket(
+        socket: UdpSocket,
+        remote_addr: String,
+        name: String,
+        config: SenderConfig,
+    ) -> Result<Self, SenderError> {
+        let local_addr = socket.local_addr()?;
 
-        thread::spawn(move || loop {
-            let msg = format!("{}:\t{}", name, value);
-            if let Err(e) = socket.send_to(&msg.to_string().into_bytes(), remote_addr.as_str()) {
-                println!("cannot send data to {}: {}", remote_addr, e);
-            }
-            thread::sleep(Duration::from_millis(100));
+        config.validate()?;
+        let kind = config.kind;
+        let unit = config.unit();
+        let telemetry = Telemetry {
+            device: name.clone(),
+            kind,
+            unit,
+            value: config.generator.initial(),
+            seq: 0,
+            sent_at: SystemTime::now(),
+        };
+        let shared = Arc::new(Shared {
+            settings: Mutex::new(Settings {
+                config,
+                remote_addr,
+                stopped: false,
+                changed: false,
+            }),
+            wake: Condvar::new(),
+            sent: AtomicU64::new(0),
+            lost: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
         });
-        Ok(Self {})
+        let _shared = shared.clone();
+        let handle = thread::spawn(move || run(socket, telemetry, &_shared));
+        Ok(Self {
+            name,
+            kind,
+            local_addr,
+            responder: None,
+            shared,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> MeasurementKind {
+        self.kind
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn stats(&self) -> SenderStats {
+        SenderStats {
+            sent: self.shared.sent.load(Ordering::Relaxed),
+            lost: self.shared.lost.load(Ordering::Relaxed),
+            failed: self.shared.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Sends `value` from now on, whatever the generator was.
+    pub fn set_value(&self, value: f64) {
+        self.update(|settings| settings.config.generator = Generator::Constant(value));
+    }
+
+    pub fn set_generator(&self, generator: Generator) {
+        self.update(|settings| settings.config.generator = generator);
+    }
+
+    /// Pauses for `interval` between datagrams, but at least `MIN_INTERVAL`.
+    pub fn set_interval(&self, interval: Duration) {
+        let interval = interval.max(MIN_INTERVAL);
+        self.update(|settings| settings.config.interval = interval);
+    }
+
+    /// Sends to `remote_addr` from now on. It is resolved on every send, so
+    /// a bad address shows up as send errors rather than here.
+    pub fn set_remote(&self, remote_addr: String) {
+        self.update(|settings| settings.remote_addr = remote_addr);
+    }
+
+    /// Stops sending and waits for the thread to finish. Does nothing if
+    /// the sender has been stopped already.
+    pub fn stop(&mut self) {
+        self.update(|settings| settings.stopped = true);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    fn update<F: FnOnce(&mut Settings)>(&self, f: F) {
+        let mut settings = self.shared.settings.lock().unwrap();
+        f(&mut settings);
+        settings.changed = true;
+        self.shared.wake.notify_one();
+    }
+
+    /// Makes the thermometer answer discovery probes arriving at `addr`.
+    pub fn enable_discovery<Addrs>(&mut self, addr: Addrs) -> io::Result<()>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let announcement = Announcement {
+            kind: DeviceKind::Thermometer,
+            name: self.name.clone(),
+            address: self.local_addr,
+        };
+        self.responder = Some(Responder::spawn(addr, announcement)?);
+        Ok(())
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run(socket: UdpSocket, mut telemetry: Telemetry, shared: &Shared) {
+    let started = Instant::now();
+    let mut settings = shared.settings.lock().unwrap();
+    while !settings.stopped {
+        let config = &settings.config;
+        telemetry.value = config.generator.sample(started.elapsed(), telemetry.value);
+        telemetry.sent_at = SystemTime::now();
+        // a lost datagram still takes a sequence number, as it would on a real network
+        if config.is_lost() {
+            shared.lost.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let remote_addr = settings.remote_addr.clone();
+            let datagram = telemetry.encode();
+            drop(settings);
+            match socket.send_to(&datagram, remote_addr.as_str()) {
+                Ok(_) => shared.sent.fetch_add(1, Ordering::Relaxed),
+                Err(_) => shared.failed.fetch_add(1, Ordering::Relaxed),
+            };
+            settings = shared.settings.lock().unwrap();
+        }
+        telemetry.seq += 1;
+
+        let pause = settings.config.pause();
+        settings = shared
+            .wake
+            .wait_timeout_while(settings, pause, |s| !s.stopped && !s.changed)
+            .unwrap()
+            .0;
+        settings.changed = false;
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SenderError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0} cannot be measured in {1}")]
+    UnitMismatch(MeasurementKind, Unit),
+    #[error("interval must not be zero")]
+    ZeroInterval,
+    #[error("jitter {0} is not from 0 to 1")]
+    InvalidJitter(f64),
+    #[error("loss {0} is not from 0 to 1")]
+    InvalidLoss(f64),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datagram::{self, Datagram};
+
+    use super::*;
+
+    fn receive(socket: &UdpSocket) -> Telemetry {
+        let mut buf = [0; 512];
+        let n = socket.recv(&mut buf).unwrap();
+        match datagram::parse(&buf[..n]).unwrap() {
+            Datagram::Telemetry(telemetry) => telemetry,
+            other => panic!("expected telemetry, got {:?}", other),
+        }
+    }
+
+    fn bind() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        socket
+    }
+
+    #[test]
+    fn test_update_and_stop() {
+        let first = bind();
+        let second = bind();
+        let mut sender = Sender::new(
+            "127.0.0.1:0".into(),
+            first.local_addr().unwrap().to_string(),
+            "balcony".into(),
+            20.0,
+        )
+        .unwrap();
+        sender.set_interval(Duration::from_secs(60));
+
+        let telemetry = receive(&first);
+        assert_eq!(
+            (telemetry.device.as_str(), telemetry.value),
+            ("balcony", 20.0)
+        );
+
+        // every update is sent right away, the long interval notwithstanding
+        sender.set_value(-3.5);
+        while receive(&first).value != -3.5 {}
+
+        sender.set_remote(second.local_addr().unwrap().to_string());
+        assert_eq!(receive(&second).value, -3.5);
+
+        sender.stop();
+        sender.stop();
+        second.set_nonblocking(true).unwrap();
+        let mut buf = [0; 512];
+        while second.recv(&mut buf).is_ok() {}
+        thread::sleep(Duration::from_millis(50));
+        assert!(second.recv(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_declared_unit() {
+        let receiver = bind();
+        let remote = receiver.local_addr().unwrap().to_string();
+        let config = SenderConfig {
+            unit: Some(Unit::Fahrenheit),
+            generator: Generator::Constant(70.0),
+            ..SenderConfig::default()
+        };
+        let _sender = Sender::with_config(
+            "127.0.0.1:0".into(),
+            remote.clone(),
+            "porch".into(),
+            config.clone(),
+        )
+        .unwrap();
+        let telemetry = receive(&receiver);
+        assert_eq!((telemetry.unit, telemetry.value), (Unit::Fahrenheit, 70.0));
+
+        let config = SenderConfig {
+            kind: MeasurementKind::Humidity,
+            ..config
+        };
+        assert!(matches!(
+            Sender::with_config("127.0.0.1:0".into(), remote, "porch".into(), config),
+            Err(SenderError::UnitMismatch(
+                MeasurementKind::Humidity,
+                Unit::Fahrenheit
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_validate_config() {
+        assert!(SenderConfig::default().validate().is_ok());
+        let config = |f: fn(&mut SenderConfig)| {
+            let mut config = SenderConfig::default();
+            f(&mut config);
+            config.validate()
+        };
+        assert!(matches!(
+            config(|c| c.interval = Duration::ZERO),
+            Err(SenderError::ZeroInterval)
+        ));
+        assert!(matches!(
+            config(|c| c.jitter = f64::NAN),
+            Err(SenderError::InvalidJitter(_))
+        ));
+        assert!(matches!(
+            config(|c| c.loss = 1.5),
+            Err(SenderError::InvalidLoss(_))
+        ));
+    }
+
+    #[test]
+    fn test_share_socket_and_count() {
+        let receiver = bind();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = SenderConfig {
+            interval: Duration::from_millis(10),
+            ..SenderConfig::default()
+        };
+        let remote = receiver.local_addr().unwrap().to_string();
+        let mut sending = Sender::with_socket(
+            socket.try_clone().unwrap(),
+            remote.clone(),
+            "sending".into(),
+            config.clone(),
+        )
+        .unwrap();
+        let mut losing = Sender::with_socket(
+            socket,
+            remote,
+            "losing".into(),
+            SenderConfig {
+                loss: 1.0,
+                ..config
+            },
+        )
+        .unwrap();
+        assert_eq!(sending.local_addr(), losing.local_addr());
+
+        assert_eq!(receive(&receiver).device, "sending");
+        thread::sleep(Duration::from_millis(50));
+        sending.stop();
+        losing.stop();
+
+        let stats = sending.stats();
+        assert!(stats.sent > 0);
+        assert_eq!((stats.lost, stats.failed), (0, 0));
+        let stats = losing.stats();
+        assert!(stats.lost > 0);
+        assert_eq!((stats.sent, stats.failed), (0, 0));
+    }
+
+    #[test]
+    fn test_drop_stops_many_senders() {
+        let socket = bind();
+        let senders: Vec<_> = (0..20)
+            .map(|i| {
+                Sender::new(
+                    "127.0.0.1:0".into(),
+                    socket.local_addr().unwrap().to_string(),
+                    format!("sensor {}", i),
+                    i as f64,
+                )
+                .unwrap()
+            })
+            .collect();
+        receive(&socket);
+        drop(senders);
+
+        socket.set_nonblocking(true).unwrap();
+        let mut buf = [0; 512];
+        while socket.recv(&mut buf).is_ok() {}
+        thread::sleep(Duration::from_millis(150));
+        assert!(socket.recv(&mut buf).is_err());
     }
 }
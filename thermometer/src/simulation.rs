@@ -0,0 +1,239 @@
+use std::f64::consts::TAU;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use sampling::{parse_csv, InvalidCsvRow};
+use thiserror::Error;
+
+/// Produces the values a simulated sensor sends, e.g. temperatures.
+///
+/// Generators are written as `constant:<value>`, `sine:<mean>:<amplitude>:<period s>`,
+/// `random:<min>:<max>:<step>`, `step:<every s>:<value>,<value>,...` or
+/// `replay:<path to csv>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Generator {
+    Constant(f64),
+    /// Swings around `mean`, e.g. a day and night cycle with a period of a day.
+    Sine {
+        mean: f64,
+        amplitude: f64,
+        period: Duration,
+    },
+    /// Moves by at most `step` degrees on every sample, staying in `min..=max`.
+    RandomWalk {
+        min: f64,
+        max: f64,
+        step: f64,
+    },
+    /// Jumps to the next of `values` every `every`, starting over after the last.
+    Steps {
+        every: Duration,
+        values: Vec<f64>,
+    },
+    /// Repeats `seconds,degrees` rows of a CSV file in a loop, starting over
+    /// at the time of the last row.
+    Replay {
+        path: PathBuf,
+        samples: Vec<(Duration, f64)>,
+    },
+}
+
+impl Generator {
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self, GeneratorError> {
+        let path = path.as_ref();
+        let samples = parse_csv(&fs::read_to_string(path)?)?;
+        Ok(Generator::Replay {
+            path: path.to_owned(),
+            samples,
+        })
+    }
+
+    /// Returns the temperature `elapsed` after the thermometer started,
+    /// given the previous sample `last`.
+    pub fn sample(&self, elapsed: Duration, last: f64) -> f64 {
+        match self {
+            Generator::Constant(value) => *value,
+            Generator::Sine {
+                mean,
+                amplitude,
+                period,
+            } => {
+                if period.is_zero() {
+                    return *mean;
+                }
+                let phase = elapsed.as_secs_f64() / period.as_secs_f64();
+                mean + amplitude * (TAU * phase).sin()
+            }
+            Generator::RandomWalk { min, max, step } => {
+                sampling::random_walk(last, *min, *max, *step)
+            }
+            Generator::Steps { every, values } => {
+                if every.is_zero() || values.is_empty() {
+                    return values.first().copied().unwrap_or(0.0);
+                }
+                let step = elapsed.as_nanos() / every.as_nanos();
+                values[(step % values.len() as u128) as usize]
+            }
+            Generator::Replay { samples, .. } => sampling::replay(samples, elapsed),
+        }
+    }
+
+    /// The value a fresh thermometer starts from.
+    pub fn initial(&self) -> f64 {
+        match self {
+            Generator::RandomWalk { min, max, .. } => (min + max) / 2.0,
+            _ => self.sample(Duration::ZERO, 0.0),
+        }
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Generator::Constant(value) => write!(f, "constant:{}", value),
+            Generator::Sine {
+                mean,
+                amplitude,
+                period,
+            } => write!(f, "sine:{}:{}:{}", mean, amplitude, period.as_secs_f64()),
+            Generator::RandomWalk { min, max, step } => {
+                write!(f, "random:{}:{}:{}", min, max, step)
+            }
+            Generator::Steps { every, values } => {
+                let values: Vec<_> = values.iter().map(f64::to_string).collect();
+                write!(f, "step:{}:{}", every.as_secs_f64(), values.join(","))
+            }
+            Generator::Replay { path, .. } => write!(f, "replay:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for Generator {
+    type Err = GeneratorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || GeneratorError::InvalidGenerator(s.to_owned());
+        let number = |v: &str| {
+            v.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(invalid)
+        };
+        let seconds =
+            |v: &str| number(v).and_then(|v| Duration::try_from_secs_f64(v).map_err(|_| invalid()));
+
+        let (kind, args) = s.trim().split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "constant" => Ok(Generator::Constant(number(args)?)),
+            "sine" => match args.split(':').collect::<Vec<_>>()[..] {
+                [mean, amplitude, period] => Ok(Generator::Sine {
+                    mean: number(mean)?,
+                    amplitude: number(amplitude)?,
+                    period: seconds(period)?,
+                }),
+                _ => Err(invalid()),
+            },
+            "random" => match args.split(':').collect::<Vec<_>>()[..] {
+                [min, max, step] => {
+                    let (min, max, step) = (number(min)?, number(max)?, number(step)?);
+                    if min > max || step < 0.0 {
+                        return Err(invalid());
+                    }
+                    Ok(Generator::RandomWalk { min, max, step })
+                }
+                _ => Err(invalid()),
+            },
+            "step" => {
+                let (every, values) = args.split_once(':').ok_or_else(invalid)?;
+                let values = values
+                    .split(',')
+                    .map(number)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Generator::Steps {
+                    every: seconds(every)?,
+                    values,
+                })
+            }
+            "replay" => Generator::replay(args),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum GeneratorError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid generator: {0}")]
+    InvalidGenerator(String),
+    #[error("invalid csv row {0}")]
+    InvalidCsvRow(usize),
+}
+
+impl From<InvalidCsvRow> for GeneratorError {
+    fn from(e: InvalidCsvRow) -> Self {
+        GeneratorError::InvalidCsvRow(e.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sine_cycle() {
+        let generator: Generator = "sine:20:5:86400".parse().unwrap();
+        let at = |hours: u64| generator.sample(Duration::from_secs(hours * 3600), 0.0);
+        assert!((at(0) - 20.0).abs() < 1e-9);
+        assert!((at(6) - 25.0).abs() < 1e-9);
+        assert!((at(18) - 15.0).abs() < 1e-9);
+        assert!((at(24) - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_random_walk_stays_in_bounds() {
+        let generator: Generator = "random:-5:5:1".parse().unwrap();
+        let mut value = generator.initial();
+        for i in 0..1000 {
+            value = generator.sample(Duration::from_secs(i), value);
+            assert!((-5.0..=5.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn test_steps_and_replay_loop() {
+        let generator: Generator = "step:10:18,22".parse().unwrap();
+        assert_eq!(generator.sample(Duration::from_secs(5), 0.0), 18.0);
+        assert_eq!(generator.sample(Duration::from_secs(15), 0.0), 22.0);
+        assert_eq!(generator.sample(Duration::from_secs(25), 0.0), 18.0);
+
+        let generator = Generator::Replay {
+            path: PathBuf::from("temperature.csv"),
+            samples: parse_csv("seconds,degrees\n0,20\n10,-3.5\n20,20\n").unwrap(),
+        };
+        assert_eq!(generator.sample(Duration::from_secs(3), 0.0), 20.0);
+        assert_eq!(generator.sample(Duration::from_secs(15), 0.0), -3.5);
+        assert_eq!(generator.sample(Duration::from_secs(32), 0.0), -3.5);
+    }
+
+    #[test]
+    fn test_parse_generator() {
+        for s in [
+            "constant:21.5",
+            "sine:20:5:86400",
+            "random:-1:2.5:0.5",
+            "step:0.5:18,22",
+        ] {
+            assert_eq!(s.parse::<Generator>().unwrap().to_string(), s);
+        }
+        assert!("random:5:1:1".parse::<Generator>().is_err());
+        assert!("sine:20:5:-1".parse::<Generator>().is_err());
+        assert!("step:10:".parse::<Generator>().is_err());
+        assert!("solar:1".parse::<Generator>().is_err());
+    }
+}
@@ -1,10 +1,10 @@
-use std::sync::Mutex;
-
 use actix_web::{delete, post, web, HttpResponse};
+use smart::devices::sensor::Sensor;
 use smart::devices::smartsocket::SmartSocket;
 use smart::devices::thermometer::Thermometer;
 use smart::devices::types::DeviceType;
 use smart::house;
+use tokio::sync::Mutex;
 
 use crate::errors::JsonError;
 
@@ -21,7 +21,7 @@ async fn add_device(
     room_name: web::Path<String>,
     req: web::Json<AddDeviceRequest>,
 ) -> HttpResponse {
-    match house.lock().unwrap().get_room_mut(room_name.as_str()) {
+    match house.lock().await.get_room_mut(room_name.as_str()) {
         None => HttpResponse::NotFound().body(""),
         Some(room) => match req.device_type.as_str() {
             "socket" => match room.add_device(DeviceType::SmartSocket(SmartSocket::new(
@@ -29,7 +29,7 @@ async fn add_device(
                 &req.description,
             ))) {
                 Err(e) => HttpResponse::BadRequest()
-                    .header("Content-Type", "application/json")
+                    .content_type("application/json")
                     .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
                 Ok(()) => HttpResponse::Created().body(""),
             },
@@ -38,12 +38,20 @@ async fn add_device(
                 &req.description,
             ))) {
                 Err(e) => HttpResponse::BadRequest()
-                    .header("Content-Type", "application/json")
+                    .content_type("application/json")
+                    .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
+                Ok(()) => HttpResponse::Created().body(""),
+            },
+            "sensor" => match room
+                .add_device(DeviceType::Sensor(Sensor::new(&req.name, &req.description)))
+            {
+                Err(e) => HttpResponse::BadRequest()
+                    .content_type("application/json")
                     .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
                 Ok(()) => HttpResponse::Created().body(""),
             },
             _ => HttpResponse::BadRequest()
-                .header("Content-Type", "application/json")
+                .content_type("application/json")
                 .body(
                     serde_json::to_string(&JsonError::new("unknown device type".into())).unwrap(),
                 ),
@@ -62,11 +70,11 @@ async fn remove_device(
     room_name: web::Path<String>,
     req: web::Json<RemoveDeviceRequest>,
 ) -> HttpResponse {
-    match house.lock().unwrap().get_room_mut(room_name.as_str()) {
+    match house.lock().await.get_room_mut(room_name.as_str()) {
         None => HttpResponse::NotFound().body(""),
         Some(room) => match room.remove_device(&req.name) {
             Err(e) => HttpResponse::BadRequest()
-                .header("Content-Type", "application/json")
+                .content_type("application/json")
                 .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
             Ok(()) => HttpResponse::NoContent().body(""),
         },
@@ -1,9 +1,11 @@
-use std::sync::Mutex;
-
 use actix_web::{get, web, HttpResponse};
 use smart::devices::device::Device;
 use smart::formatter::JsonFormatter;
 use smart::house;
+use thermometer::datagram::TemperatureUnit;
+use tokio::sync::Mutex;
+
+use crate::errors::JsonError;
 
 #[derive(serde::Serialize)]
 struct Room {
@@ -38,7 +40,7 @@ impl RoomDevice {
 #[get("/rooms")]
 pub async fn get_rooms(house: web::Data<Mutex<house::House>>) -> HttpResponse {
     let mut rooms: Vec<Room> = Vec::new();
-    for room in house.lock().unwrap().get_rooms() {
+    for room in house.lock().await.get_rooms() {
         let devices = room
             .get_devices()
             .map(|d| RoomDevice::new(d.get_name(), d.get_description()))
@@ -48,15 +50,39 @@ pub async fn get_rooms(house: web::Data<Mutex<house::House>>) -> HttpResponse {
     HttpResponse::Ok().body(serde_json::to_string(&rooms).unwrap())
 }
 
+#[derive(serde::Deserialize)]
+struct ReportQuery {
+    /// C, F or K; each thermometer's own setting if not given.
+    units: Option<String>,
+}
+
 #[get("/report")]
-pub async fn get_report(house: web::Data<Mutex<house::House>>) -> HttpResponse {
-    HttpResponse::Ok()
-        .header("Content-Type", "application/json")
-        .body(
+pub async fn get_report(
+    house: web::Data<Mutex<house::House>>,
+    query: web::Query<ReportQuery>,
+) -> HttpResponse {
+    let units = match query.units.as_deref().map(str::parse::<TemperatureUnit>) {
+        None => None,
+        Some(Ok(units)) => Some(units),
+        Some(Err(_)) => {
+            return HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string(&JsonError::new("units must be one of C, F or K".into()))
+                        .unwrap(),
+                )
+        }
+    };
+    let house = house.lock().await;
+    let report = match units {
+        Some(units) => {
             house
-                .lock()
-                .unwrap()
-                .summary_fmt(Box::new(JsonFormatter {}))
-                .await,
-        )
+                .summary_fmt_in(Box::new(JsonFormatter {}), units)
+                .await
+        }
+        None => house.summary_fmt(Box::new(JsonFormatter {})).await,
+    };
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(report)
 }
@@ -3,3 +3,4 @@ pub mod errors;
 pub mod house;
 pub mod rooms;
 pub mod socket;
+pub mod thermometer;
@@ -1,6 +1,5 @@
-use std::sync::Mutex;
-
 use actix_web::{web, App, HttpServer};
+use tokio::sync::Mutex;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -17,6 +16,9 @@ async fn main() -> std::io::Result<()> {
             .service(::web::devices::remove_device)
             .service(::web::socket::connect_socket)
             .service(::web::socket::switch_socket)
+            .service(::web::socket::set_socket_state)
+            .service(::web::socket::get_socket_connection)
+            .service(::web::thermometer::get_thermometer_history)
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -1,7 +1,6 @@
-use std::sync::Mutex;
-
 use actix_web::{delete, post, put, web, HttpResponse};
 use smart::{devices::device::Device, devices::types::DeviceType, house};
+use tokio::sync::Mutex;
 
 use crate::errors::JsonError;
 
@@ -15,9 +14,9 @@ async fn add_room(
     house: web::Data<Mutex<house::House>>,
     req: web::Json<AddRoomRequest>,
 ) -> HttpResponse {
-    match house.lock().unwrap().add_room(&req.name) {
+    match house.lock().await.add_room(&req.name) {
         Err(e) => HttpResponse::BadRequest()
-            .header("Content-Type", "application/json")
+            .content_type("application/json")
             .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
         Ok(()) => HttpResponse::Created().body(""),
     }
@@ -33,9 +32,9 @@ async fn remove_room(
     house: web::Data<Mutex<house::House>>,
     req: web::Json<RemoveRoomRequest>,
 ) -> HttpResponse {
-    match house.lock().unwrap().remove_room(&req.name) {
+    match house.lock().await.remove_room(&req.name) {
         Err(e) => HttpResponse::BadRequest()
-            .header("Content-Type", "application/json")
+            .content_type("application/json")
             .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
         Ok(()) => HttpResponse::NoContent().body(""),
     }
@@ -52,17 +51,17 @@ async fn mount_receiver(
     room_name: web::Path<String>,
     req: web::Json<MountReceiverRequest>,
 ) -> HttpResponse {
-    match house.lock().unwrap().get_room_mut(&room_name) {
+    match house.lock().await.get_room_mut(&room_name) {
         None => HttpResponse::NotFound().body(""),
         Some(room) => match room.mount_receiver(&req.address).await {
             Err(e) => HttpResponse::InternalServerError()
-                .header("Content-Type", "application/json")
+                .content_type("application/json")
                 .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
             Ok(()) => {
                 let mut thermometers: Vec<String> = Vec::new();
                 for device in room.get_devices_mut() {
-                    if let DeviceType::Thermometer(t) = device {
-                        thermometers.push(t.get_name().into());
+                    if let DeviceType::Thermometer(_) | DeviceType::Sensor(_) = device {
+                        thermometers.push(device.get_name().into());
                     }
                 }
                 thermometers
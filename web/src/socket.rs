@@ -1,7 +1,11 @@
-use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
 
-use actix_web::{post, put, web, HttpResponse};
+use actix_web::{get, post, put, web, HttpResponse};
+use smart::connection::ConnectionState;
+use smart::devices::circuit_breaker::BreakerState;
 use smart::house;
+use smart_socket::tls;
+use tokio::sync::Mutex;
 
 use crate::errors::JsonError;
 
@@ -9,6 +13,13 @@ use crate::errors::JsonError;
 struct ConnectSocketRequest {
     name: String,
     host: String,
+    /// Shared secret for sockets started with `--secret`.
+    #[serde(default)]
+    secret: Option<String>,
+    /// PEM certificate to trust for sockets serving TLS, the text itself
+    /// rather than a path, so that callers cannot make the server read files.
+    #[serde(default)]
+    ca_cert: Option<String>,
 }
 
 #[put("/room/{room_name}/socket/connect")]
@@ -17,16 +28,33 @@ async fn connect_socket(
     room_name: web::Path<String>,
     req: web::Json<ConnectSocketRequest>,
 ) -> HttpResponse {
-    match house.lock().unwrap().get_room_mut(&room_name) {
+    match house.lock().await.get_room_mut(&room_name) {
         None => HttpResponse::NotFound().body(""),
         Some(room) => match room.get_socket_mut(&req.name) {
             None => HttpResponse::NotFound().body(""),
-            Some(socket) => match socket.connect(&req.host).await {
-                Err(e) => HttpResponse::InternalServerError()
-                    .header("Content-Type", "application/json")
-                    .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
-                Ok(()) => HttpResponse::Ok().body(""),
-            },
+            Some(socket) => {
+                if let Some(secret) = &req.secret {
+                    socket.set_secret(secret);
+                }
+                if let Some(pem) = &req.ca_cert {
+                    match tls::client_config_from_pem(pem.as_bytes()) {
+                        Ok(config) => socket.set_tls(config),
+                        Err(e) => {
+                            return HttpResponse::BadRequest()
+                                .content_type("application/json")
+                                .body(
+                                    serde_json::to_string(&JsonError::new(e.to_string())).unwrap(),
+                                )
+                        }
+                    }
+                }
+                match socket.connect(&req.host).await {
+                    Err(e) => HttpResponse::InternalServerError()
+                        .content_type("application/json")
+                        .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
+                    Ok(()) => HttpResponse::Ok().body(""),
+                }
+            }
         },
     }
 }
@@ -42,16 +70,141 @@ async fn switch_socket(
     room_name: web::Path<String>,
     req: web::Json<SwitchSocketRequest>,
 ) -> HttpResponse {
-    match house.lock().unwrap().get_room_mut(&room_name) {
+    match house.lock().await.get_room_mut(&room_name) {
         None => HttpResponse::NotFound().body(""),
         Some(room) => match room.get_socket_mut(&req.name) {
             None => HttpResponse::NotFound().body(""),
             Some(socket) => match socket.switch().await {
                 Err(e) => HttpResponse::InternalServerError()
-                    .header("Content-Type", "application/json")
+                    .content_type("application/json")
                     .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
                 Ok(()) => HttpResponse::Ok().body(""),
             },
         },
     }
 }
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SocketTargetState {
+    On,
+    Off,
+}
+
+#[derive(serde::Deserialize)]
+struct SetSocketStateRequest {
+    name: String,
+    state: SocketTargetState,
+}
+
+#[put("/room/{room_name}/socket/state")]
+async fn set_socket_state(
+    house: web::Data<Mutex<house::House>>,
+    room_name: web::Path<String>,
+    req: web::Json<SetSocketStateRequest>,
+) -> HttpResponse {
+    match house.lock().await.get_room_mut(&room_name) {
+        None => HttpResponse::NotFound().body(""),
+        Some(room) => match room.get_socket_mut(&req.name) {
+            None => HttpResponse::NotFound().body(""),
+            Some(socket) => {
+                let res = match req.state {
+                    SocketTargetState::On => socket.turn_on().await,
+                    SocketTargetState::Off => socket.turn_off().await,
+                };
+                match res {
+                    Err(e) => HttpResponse::InternalServerError()
+                        .content_type("application/json")
+                        .body(serde_json::to_string(&JsonError::new(e.to_string())).unwrap()),
+                    Ok(()) => HttpResponse::Ok().body(""),
+                }
+            }
+        },
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SocketConnection {
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempt: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Unix time of the failure, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<u64>,
+    breaker: SocketBreaker,
+}
+
+#[derive(serde::Serialize)]
+struct SocketBreaker {
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_in_secs: Option<u64>,
+}
+
+impl From<BreakerState> for SocketBreaker {
+    fn from(state: BreakerState) -> Self {
+        match state {
+            BreakerState::Closed { .. } => Self {
+                state: "closed",
+                retry_in_secs: None,
+            },
+            BreakerState::Open { retry_in } => Self {
+                state: "open",
+                retry_in_secs: Some(retry_in.as_secs()),
+            },
+            BreakerState::HalfOpen => Self {
+                state: "half-open",
+                retry_in_secs: None,
+            },
+        }
+    }
+}
+
+impl SocketConnection {
+    fn new(state: ConnectionState, breaker: BreakerState) -> Self {
+        let mut res = Self {
+            state: "",
+            attempt: None,
+            error: None,
+            since: None,
+            breaker: breaker.into(),
+        };
+        match state {
+            ConnectionState::Disconnected => res.state = "disconnected",
+            ConnectionState::Connected => res.state = "connected",
+            ConnectionState::Reconnecting { attempt } => {
+                res.state = "reconnecting";
+                res.attempt = Some(attempt);
+            }
+            ConnectionState::Down { error, at } => {
+                res.state = "down";
+                res.error = Some(error);
+                res.since = at.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+            }
+        }
+        res
+    }
+}
+
+#[get("/room/{room_name}/socket/{socket_name}/connection")]
+async fn get_socket_connection(
+    house: web::Data<Mutex<house::House>>,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    let (room_name, socket_name) = path.into_inner();
+    match house.lock().await.get_room(&room_name) {
+        None => HttpResponse::NotFound().body(""),
+        Some(room) => match room.get_socket(&socket_name) {
+            None => HttpResponse::NotFound().body(""),
+            Some(socket) => HttpResponse::Ok().content_type("application/json").body(
+                serde_json::to_string(&SocketConnection::new(
+                    socket.connection_state(),
+                    socket.breaker_state(),
+                ))
+                .unwrap(),
+            ),
+        },
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use actix_web::{get, web, HttpResponse};
+use smart::history::{Point, Stats};
+use smart::house;
+use tokio::sync::Mutex;
+
+/// An hour, unless the request asks for another window.
+const DEFAULT_WINDOW_SECS: u64 = 60 * 60;
+
+#[derive(serde::Deserialize)]
+struct HistoryQuery {
+    /// How far back to look, in seconds.
+    window: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+struct TemperatureHistory {
+    stats: Option<TemperatureStats>,
+    points: Vec<TemperaturePoint>,
+}
+
+#[derive(serde::Serialize)]
+struct TemperatureStats {
+    min: f64,
+    max: f64,
+    avg: f64,
+    /// Degrees per hour.
+    trend: f64,
+    samples: u64,
+}
+
+impl From<Stats> for TemperatureStats {
+    fn from(stats: Stats) -> Self {
+        Self {
+            min: stats.min,
+            max: stats.max,
+            avg: stats.avg,
+            trend: stats.trend,
+            samples: stats.samples,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct TemperaturePoint {
+    /// Unix time of the start of the bucket, in seconds.
+    at: u64,
+    min: f64,
+    max: f64,
+    avg: f64,
+}
+
+impl From<Point> for TemperaturePoint {
+    fn from(point: Point) -> Self {
+        Self {
+            at: point
+                .at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            min: point.min,
+            max: point.max,
+            avg: point.avg,
+        }
+    }
+}
+
+#[get("/room/{room_name}/thermometer/{thermometer_name}/history")]
+async fn get_thermometer_history(
+    house: web::Data<Mutex<house::House>>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HistoryQuery>,
+) -> HttpResponse {
+    let (room_name, thermometer_name) = path.into_inner();
+    let window = Duration::from_secs(query.window.unwrap_or(DEFAULT_WINDOW_SECS));
+    match house.lock().await.get_room(&room_name) {
+        None => HttpResponse::NotFound().body(""),
+        Some(room) => match room.get_thermometer(&thermometer_name) {
+            None => HttpResponse::NotFound().body(""),
+            Some(thermometer) => HttpResponse::Ok().content_type("application/json").body(
+                serde_json::to_string(&TemperatureHistory {
+                    stats: thermometer.stats(window).map(Into::into),
+                    points: thermometer
+                        .history(window)
+                        .into_iter()
+                        .map(Into::into)
+                        .collect(),
+                })
+                .unwrap(),
+            ),
+        },
+    }
+}